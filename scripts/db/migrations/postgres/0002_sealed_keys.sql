@@ -0,0 +1,3 @@
+/* keystore 密码和客户端私钥由主密钥加密保存，加密后长度增加 */
+alter table app add column if not exists certs_passwd varchar(512);
+alter table app_client_key alter column prikey type text;
@@ -0,0 +1,9 @@
+/*
+    客户端密钥版本
+    同一 serialid 可以有多个版本，expires_at 之前旧版本仍然可用(unix 时间戳秒，null 表示不过期)
+*/
+alter table app_client_key add column if not exists key_version bigint not null default 1;
+alter table app_client_key add column if not exists expires_at bigint;
+alter table app_client_key drop constraint if exists app_client_key_pkey;
+alter table app_client_key add primary key (app_id, client_type, serialid, key_version);
+create index if not exists idx_app_client_key_serialid on app_client_key(serialid);
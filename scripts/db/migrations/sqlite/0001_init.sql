@@ -0,0 +1,35 @@
+/* 项目详细信息表 */
+create table if not exists app(
+    id integer primary key autoincrement,
+    name text not null,
+    description text,
+    certs blob -- 存储多证书keystore
+);
+
+/*
+    项目关联客户端私钥管理
+    client_type 0 ios;1 android;2 harmony
+*/
+create table if not exists app_client_key(
+    app_id integer not null,
+    client_type integer not null,
+    serialid text not null, -- 唯一标识
+    pubkey text, -- 公钥
+    prikey text, -- 私钥
+    primary key (app_id, client_type, serialid)
+);
+
+/* 网关接口 */
+create table if not exists gateway_api (
+    app_id integer not null,
+    api_name text not null,
+    hosts text not null,
+    req_path text not null,
+    req_method text default 'POST',
+    threshold_sec integer default 0,
+    data_req_example text,
+    data_resp_example text,
+    use_state integer default 1,
+    load_balance integer default 0,
+    primary key (app_id, api_name)
+);
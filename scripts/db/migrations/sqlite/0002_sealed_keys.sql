@@ -0,0 +1,2 @@
+/* keystore 密码由主密钥加密保存 */
+alter table app add column certs_passwd text;
@@ -0,0 +1,3 @@
+/* 轮换证书库时保留上一版，新证书库不可用时握手回退到上一版 */
+alter table app add column prev_certs blob;
+alter table app add column prev_certs_passwd text;
@@ -0,0 +1,20 @@
+/*
+    客户端密钥版本
+    同一 serialid 可以有多个版本，expires_at 之前旧版本仍然可用(unix 时间戳秒，null 表示不过期)
+    sqlite 不能修改主键，重建表
+*/
+create table app_client_key_new(
+    app_id integer not null,
+    client_type integer not null,
+    serialid text not null,
+    pubkey text,
+    prikey text,
+    key_version integer not null default 1,
+    expires_at integer,
+    primary key (app_id, client_type, serialid, key_version)
+);
+insert into app_client_key_new(app_id, client_type, serialid, pubkey, prikey)
+    select app_id, client_type, serialid, pubkey, prikey from app_client_key;
+drop table app_client_key;
+alter table app_client_key_new rename to app_client_key;
+create index idx_app_client_key_serialid on app_client_key(serialid);
@@ -0,0 +1,7 @@
+/*
+    客户端密钥吊销
+    status 0 正常 1 已吊销，吊销后保留记录用于审计
+*/
+alter table app_client_key add column status integer not null default 0;
+alter table app_client_key add column revoked_reason text;
+alter table app_client_key add column revoked_at integer;
@@ -0,0 +1,55 @@
+/*
+   业务数据处理
+   data_type 3 的报文使用协商出的对称密钥解密后交给业务处理器，
+   处理结果由 common_pack 重新选择混淆模式 x/y 并加密返回
+*/
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+
+use crate::{error, store::cache::Session};
+
+// 业务请求上下文
+pub struct Context {
+    pub peer_addr: SocketAddr,
+    // 外层 TLS 客户端证书 CN
+    pub client_subject: Option<String>,
+    // 匹配的 SNI 主机名
+    pub hostname: Option<String>,
+    pub session: Session,
+}
+
+#[async_trait]
+pub trait BusinessHandler: Send + Sync {
+    // data 为解密后的明文，返回值为待加密的明文
+    async fn handle(&self, ctx: &Context, data: Vec<u8>) -> error::Result<Vec<u8>>;
+}
+
+// 默认处理器，原样返回请求数据
+pub struct EchoHandler;
+
+#[async_trait]
+impl BusinessHandler for EchoHandler {
+    async fn handle(&self, _ctx: &Context, data: Vec<u8>) -> error::Result<Vec<u8>> {
+        Ok(data)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref HANDLER: RwLock<Arc<dyn BusinessHandler>> = RwLock::new(Arc::new(EchoHandler));
+}
+
+/*
+   注册业务处理器，替换默认的 EchoHandler
+*/
+pub fn register_handler(handler: Arc<dyn BusinessHandler>) {
+    *HANDLER.write().unwrap() = handler;
+}
+
+pub fn handler() -> Arc<dyn BusinessHandler> {
+    HANDLER.read().unwrap().clone()
+}
@@ -0,0 +1,185 @@
+/*
+   报文编解码
+   报文头 0xF0 ... 报文尾 0xFE，除数据段外固定 62 字节，9..13 为数据段混淆后长度
+   1 读取不完整时等待后续数据
+   2 一次读取多个报文时逐个返回
+   3 包头之前的垃圾数据、版本不是 0x00、长度超限或者报文尾不匹配时跳过当前包头重新查找
+   4 连接关闭时仍有不完整的报文返回 DATA_INVALID
+*/
+
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    error::{self, Error, ErrorKind},
+    utils,
+};
+
+pub const FRAME_HEAD: u8 = 0xF0;
+pub const FRAME_TAIL: u8 = 0xFE;
+pub const FRAME_VERSION: u8 = 0x00;
+const FRAME_FIXED_LEN: usize = 62;
+const FRAME_LENGTH_OFFSET: usize = 9;
+
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_size: usize) -> FrameCodec {
+        FrameCodec {
+            max_frame_size: max_frame_size.max(FRAME_FIXED_LEN),
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> error::Result<Option<Vec<u8>>> {
+        loop {
+            // 清除包头之前的垃圾数据
+            match src.iter().position(|b| *b == FRAME_HEAD) {
+                Some(offset) => src.advance(offset),
+                None => {
+                    src.clear();
+                    return Ok(None);
+                }
+            }
+            if src.len() < FRAME_LENGTH_OFFSET + 4 {
+                return Ok(None);
+            }
+            if src[1] != FRAME_VERSION {
+                src.advance(1);
+                continue;
+            }
+            let data_length =
+                utils::u8_array_to_u32(&src[FRAME_LENGTH_OFFSET..FRAME_LENGTH_OFFSET + 4]);
+            let frame_len = FRAME_FIXED_LEN + data_length as usize;
+            if frame_len > self.max_frame_size {
+                src.advance(1);
+                continue;
+            }
+            if src.len() < frame_len {
+                src.reserve(frame_len - src.len());
+                return Ok(None);
+            }
+            if src[frame_len - 1] != FRAME_TAIL {
+                src.advance(1);
+                continue;
+            }
+            return Ok(Some(src.split_to(frame_len).to_vec()));
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> error::Result<Option<Vec<u8>>> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => {
+                src.clear();
+                Err(Error::new(
+                    ErrorKind::DATA_INVALID,
+                    "connection closed with incomplete frame",
+                ))
+            }
+        }
+    }
+}
+
+impl Encoder<Vec<u8>> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> Result<(), io::Error> {
+        dst.extend_from_slice(&item);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut res = vec![0; FRAME_FIXED_LEN + data.len()];
+        res[0] = FRAME_HEAD;
+        res[9..13].copy_from_slice(&utils::u32_to_vector(data.len() as u32));
+        res[61..61 + data.len()].copy_from_slice(data);
+        res[FRAME_FIXED_LEN + data.len() - 1] = FRAME_TAIL;
+        res
+    }
+
+    #[test]
+    fn partial_and_multiple() {
+        let mut codec = FrameCodec::new(1024);
+        let first = frame(&[1, 2, 3]);
+        let second = frame(&[4, 5]);
+
+        let mut buf = BytesMut::from(&first[0..20]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&first[20..]);
+        buf.extend_from_slice(&second);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(first));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(second));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn resync_on_garbage() {
+        let mut codec = FrameCodec::new(1024);
+        let valid = frame(&[1, 2, 3]);
+        let mut buf = BytesMut::from(&[1u8, 2, 3][..]);
+        // 报文尾不匹配的伪包头
+        let mut broken = frame(&[9]);
+        let len = broken.len();
+        broken[len - 1] = 0;
+        buf.extend_from_slice(&broken);
+        buf.extend_from_slice(&valid);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(valid));
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut codec = FrameCodec::new(1024);
+        let mut buf = BytesMut::from(&frame(&[1, 2])[..]);
+        buf[1] = 0x01;
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        let valid = frame(&[3]);
+        buf.extend_from_slice(&valid);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(valid));
+    }
+
+    #[test]
+    fn oversize_frame() {
+        let mut codec = FrameCodec::new(100);
+        let mut buf = BytesMut::from(&frame(&[0; 64])[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.len() < 100);
+
+        let valid = frame(&[1]);
+        buf.extend_from_slice(&valid);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(valid));
+    }
+
+    #[test]
+    fn incomplete_frame_on_eof() {
+        let mut codec = FrameCodec::new(1024);
+        let first = frame(&[1, 2, 3]);
+        let second = frame(&[4, 5]);
+        let mut buf = BytesMut::from(&first[..]);
+        buf.extend_from_slice(&second[0..20]);
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some(first));
+        let err = codec.decode_eof(&mut buf).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::DATA_INVALID));
+        assert!(buf.is_empty());
+
+        // 只有垃圾数据时正常结束
+        buf.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+}
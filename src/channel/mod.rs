@@ -1,8 +1,10 @@
+pub mod business;
+pub mod codec;
 mod security;
 mod tunnel;
 
 use crate::channel::security::datapack::common_pack;
-use crate::error;
+use crate::error::{self, Error, ErrorKind};
 use security::datapack;
 use std::net::SocketAddr;
 use std::sync::mpsc::Sender;
@@ -10,21 +12,78 @@ use std::vec;
 
 use self::security::datapack::DataEntry;
 
+/*
+   连接信息
+   client_subject 为外层 TLS 校验通过的客户端证书 CN，未提供客户端证书时为 None
+   bind_serialid 时要求 client_subject 与握手使用的 serialid 一致，未提供客户端证书的连接拒绝
+   hostname 为匹配到 [[app.sni]] 配置的 SNI 主机名
+   app_ids 非空时只允许这些项目，denied_app_ids 为限定在其他主机名上的项目
+*/
+#[derive(Clone, Debug)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub client_subject: Option<String>,
+    pub bind_serialid: bool,
+    pub hostname: Option<String>,
+    pub app_ids: Vec<usize>,
+    pub denied_app_ids: Vec<usize>,
+}
+
+impl Peer {
+    pub fn new(addr: SocketAddr) -> Peer {
+        Peer {
+            addr: addr,
+            client_subject: None,
+            bind_serialid: false,
+            hostname: None,
+            app_ids: vec![],
+            denied_app_ids: vec![],
+        }
+    }
+
+    pub fn check_app(&self, app_id: usize) -> error::Result<()> {
+        let allowed = self.app_ids.is_empty() || self.app_ids.contains(&app_id);
+        if allowed && !self.denied_app_ids.contains(&app_id) {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::APP_NOT_ALLOWED,
+            &format!(
+                "app {} not allowed on {}",
+                app_id,
+                self.hostname.as_deref().unwrap_or("default host")
+            ),
+        ))
+    }
+
+    pub fn check_serialid(&self, serialid: &str) -> error::Result<()> {
+        if !self.bind_serialid {
+            return Ok(());
+        }
+        match &self.client_subject {
+            Some(subject) if subject == serialid => Ok(()),
+            Some(_) => Err(Error::new(
+                ErrorKind::CLIENT_CERT,
+                "client certificate does not match serialid",
+            )),
+            None => Err(Error::new(
+                ErrorKind::CLIENT_CERT,
+                "client certificate required for serialid binding",
+            )),
+        }
+    }
+}
+
 /*
    主处理流程
 */
-pub fn tunnel_process(addr: &SocketAddr, data: Vec<u8>) -> Vec<u8> {
-    println!("{:#?}", addr);
+pub async fn tunnel_process(peer: &Peer, data: Vec<u8>) -> Vec<u8> {
     let mut data_entry = match datapack::common_unpack(&data) {
         Ok(data_entry) => data_entry,
-        Err(msg) => {
-            println!("data unpack error: {:?}", msg);
-            return vec![];
-        }
+        Err(err) => return error_pack(&err, &vec![]),
     };
-    println!("decrypt success!");
 
-    match process(&mut data_entry) {
+    match process(peer, &mut data_entry).await {
         Ok((data, token)) => {
             match datapack::common_pack(
                 &data,
@@ -33,29 +92,125 @@ pub fn tunnel_process(addr: &SocketAddr, data: Vec<u8>) -> Vec<u8> {
                 &token,
             ) {
                 Ok(_data) => _data,
-                Err(err) => vec![10],
-            }
-        }
-        Err(err) => {
-            match datapack::common_pack(
-                &err.to_vec(),
-                &data_entry.symmetric_key,
-                0,
-                &data_entry.token,
-            ) {
-                Ok(_data) => _data,
-                Err(err) => vec![11],
+                Err(err) => error_pack(&err, &data_entry.token),
             }
         }
+        Err(err) => session_error_pack(&err, &data_entry),
     }
 }
 
-fn process(data_entry: &mut DataEntry) -> error::Result<(Vec<u8>, Vec<u8>)> {
+/*
+   业务请求已经取得会话密钥时(如限流)，错误报文使用会话密钥加密，data_type 5
+   其他情况返回 data_type 0 错误报文
+*/
+fn session_error_pack(err: &Error, entry: &DataEntry) -> Vec<u8> {
+    if entry.data_type != 3 || entry.symmetric_key.is_empty() {
+        return error_pack(err, &entry.token);
+    }
+    eprintln!("process error {}: {:?}", err.code(), err);
+    match datapack::common_pack(
+        &err.to_vec(),
+        &entry.symmetric_key,
+        datapack::DATA_TYPE_SESSION_ERROR,
+        &entry.token,
+    ) {
+        Ok(data) => data,
+        Err(_) => error_pack(err, &entry.token),
+    }
+}
+
+/*
+   错误报文，data_type 0
+   服务端此时不一定持有客户端的对称密钥，错误报文只做混淆不加密
+   只返回错误码和固定信息，详细信息记录在服务端日志
+*/
+fn error_pack(err: &Error, token: &Vec<u8>) -> Vec<u8> {
+    eprintln!("process error {}: {:?}", err.code(), err);
+    let token = match token.len() == 40 {
+        true => token.clone(),
+        false => vec![0; 40],
+    };
+    match datapack::common_pack(&err.to_vec(), &vec![], 0, &token) {
+        Ok(_data) => _data,
+        Err(_) => vec![],
+    }
+}
+
+async fn process(peer: &Peer, data_entry: &mut DataEntry) -> error::Result<(Vec<u8>, Vec<u8>)> {
     if data_entry.data_type == 1 {
-        return tunnel::tunnel_first(&data_entry.content);
+        return tunnel::tunnel_first(peer, &data_entry.content).await;
     } else if data_entry.data_type == 2 {
-        return Ok((tunnel::tunnel_second(data_entry)?, data_entry.token.clone()));
+        return Ok((
+            tunnel::tunnel_second(data_entry).await?,
+            data_entry.token.clone(),
+        ));
+    } else if data_entry.data_type == 3 {
+        return Ok((
+            tunnel::tunnel_business(peer, data_entry).await?,
+            data_entry.token.clone(),
+        ));
+    } else if data_entry.data_type == 4 {
+        return Ok((
+            tunnel::tunnel_logout(data_entry).await?,
+            data_entry.token.clone(),
+        ));
     } else {
     }
-    Ok((vec![], vec![]))
+    Err(Error::new(ErrorKind::DATA_TYPE, "unsupported data type"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn peer_serialid() {
+        let mut peer = Peer::new("127.0.0.1:3443".parse().unwrap());
+        assert!(peer.check_serialid("abc").is_ok());
+
+        peer.client_subject = Some("abc".to_string());
+        peer.bind_serialid = true;
+        assert!(peer.check_serialid("abc").is_ok());
+        assert!(peer.check_serialid("def").is_err());
+
+        peer.bind_serialid = false;
+        assert!(peer.check_serialid("def").is_ok());
+
+        // 绑定 serialid 时未提供客户端证书的连接拒绝
+        peer.client_subject = None;
+        assert!(peer.check_serialid("abc").is_ok());
+        peer.bind_serialid = true;
+        assert!(peer.check_serialid("abc").is_err());
+        peer.bind_serialid = false;
+
+        assert!(peer.check_app(2).is_ok());
+        peer.hostname = Some("a.example.com".to_string());
+        peer.app_ids = vec![1];
+        assert!(peer.check_app(1).is_ok());
+        assert!(peer.check_app(2).is_err());
+
+        let mut peer = Peer::new("127.0.0.1:3443".parse().unwrap());
+        peer.denied_app_ids = vec![1];
+        assert!(peer.check_app(1).is_err());
+        assert!(peer.check_app(2).is_ok());
+    }
+
+    #[test]
+    fn session_error_encrypted() {
+        let token = vec![7; 40];
+        let mut entry = DataEntry::new(1, 2, &token, 3, &vec![]);
+        let err = Error::new(ErrorKind::RATE_LIMITED, "rate limit exceeded").with_retry_after(1);
+
+        // 未取得会话密钥时返回 data_type 0
+        let frame = datapack::common_unpack(&session_error_pack(&err, &entry)).unwrap();
+        assert_eq!(frame.data_type, 0);
+
+        entry.symmetric_key = (0..48).collect();
+        let mut frame = datapack::common_unpack(&session_error_pack(&err, &entry)).unwrap();
+        assert_eq!(frame.data_type, datapack::DATA_TYPE_SESSION_ERROR);
+        assert_eq!(frame.token, token);
+        assert_ne!(frame.content, err.to_vec());
+        frame.symmetric_key = entry.symmetric_key.clone();
+        assert_eq!(frame.decrypt(), err.to_vec());
+    }
 }
@@ -10,10 +10,15 @@ use std::ptr::null;
 
    报文头|版本号|时间戳|数据段混淆后长度|数据段混淆前长度|混淆模式X|混淆模式Y|数据类型|TOKEN|混淆是否启用|数据|报文尾
 
+   数据类型：0 错误 1 协商第一步 2 协商第二步 3 业务数据 4 注销会话 5 会话密钥加密的错误
+
    AES:  KEY 32bit, IV 16bit
    TOEKN 40bytes
 */
 
+// 业务请求已经取得会话密钥后的错误，数据段与 data_type 0 相同，使用会话密钥加密
+pub const DATA_TYPE_SESSION_ERROR: u8 = 5;
+
 pub struct DataEntry {
     model_x: u8,
     model_y: u8,
@@ -42,11 +47,12 @@ impl DataEntry {
         }
     }
 
+    // 与 common_pack 一致，IV 取未替换模式值的原始密钥
     pub fn decrypt(&self) -> Vec<u8> {
         let mut key = self.symmetric_key.clone();
         key[0] = self.model_x as u8;
         key[self.symmetric_key.len() - 1] = self.model_y as u8;
-        SM4::decrypt(&self.content, &key, &key[32..48].to_vec())
+        SM4::decrypt(&self.content, &key, &self.symmetric_key[32..48].to_vec())
     }
 }
 
@@ -101,10 +107,15 @@ pub fn common_pack(
     }
 
     // 协商第二步返回已经可以通过动态对称密钥加密了
-    if data_type == 1 {
+    // 错误报文 data_type 0 只做混淆
+    if data_type == 0 || data_type == 1 {
         let res = common_pack_core(data, model_x as u8, model_y as u8, data_type, token);
         Ok(res)
-    } else if data_type == 2 || data_type == 3 {
+    } else if data_type == 2
+        || data_type == 3
+        || data_type == 4
+        || data_type == DATA_TYPE_SESSION_ERROR
+    {
         // 业务数据 对称密钥
         let mut key_r = key.clone();
         key_r[0] = model_x as u8;
@@ -186,4 +197,21 @@ mod test {
         }
         println!("{}, {}", model_x, model_y);
     }
+
+    #[test]
+    fn business_decrypt() {
+        let data = vec![
+            1, 3, 52, 3, 63, 64, 63, 2, 54, 36, 92, 67, 26, 7, 46, 87, 64,
+        ];
+        let key: Vec<u8> = (0..48).collect();
+        let (model_x, model_y) = (3u8, 7u8);
+        let mut key_r = key.clone();
+        key_r[0] = model_x;
+        key_r[key.len() - 1] = model_y;
+        let ciphertext = SM4::encrypt(&data, &key_r, &key[32..48].to_vec());
+
+        let mut entry = DataEntry::new(model_x, model_y, &vec![0; 40], 3, &ciphertext);
+        entry.symmetric_key = key;
+        assert_eq!(data, entry.decrypt());
+    }
 }
@@ -1,64 +1,76 @@
 /*
    主要实现加密信道两次交互数据处理
    包括以下：
-   1 从mysql或者redis读取伪值唯一标识对应的私钥
-     先从redis读，如果没有从mysql读，并回写到redis中
+   1 读取伪值唯一标识对应的私钥和项目证书，客户端可以指定密钥版本
+     依次读进程内缓存、redis、mysql，并回写上层缓存
+     从项目证书库中随机选取一张有效证书，见 store::certpool
+     私钥和证书库密码由主密钥加密保存，仅在使用时解密
    2 生成TOKEN写入redis
        关联预值D
-   3
+   3 业务数据使用协商出的对称密钥解密后交给业务处理器
+   4 外层 TLS 校验了客户端证书时，证书 CN 需与 serialid 一致，SNI 主机名限制可用的项目，见 Peer
 */
 
+use chrono::Utc;
+
 use crate::{
     error::{self, Error, ErrorKind},
     sm::{SM2, SM3},
-    store::{
-        cache::Session,
-        db::{App, AppClientKey},
-    },
+    store::{cache::Session, certpool, db::AppClientKey, denylist, envelope, keycache},
     utils,
 };
 
-use super::security::{datapack::DataEntry, ssl};
+use super::{
+    business::{self, Context},
+    security::{datapack::DataEntry, ssl},
+    Peer,
+};
+
+// 第一个请求携带密钥版本的标记
+const KEY_VERSION_TAG: u8 = 0x00;
 
 /*
    处理协商第一个请求
 */
-pub fn tunnel_first(data: &Vec<u8>) -> error::Result<(Vec<u8>, Vec<u8>)> {
+pub async fn tunnel_first(peer: &Peer, data: &Vec<u8>) -> error::Result<(Vec<u8>, Vec<u8>)> {
+    if data.len() <= 32 {
+        return Err(Error::new(
+            ErrorKind::DATA_INVALID,
+            "first request too short",
+        ));
+    }
     let data_hash = SM3::hash(&data);
     let unique_id = data[0..32].to_vec();
     let id = String::from_utf8(unique_id)?;
-    let (app_id, private_key) = match AppClientKey::get_with_app_client(id.as_str())? {
-        Some(app_client_key) => (app_client_key.app_id, app_client_key.prikey.unwrap()),
-        None => {
-            return Err(Error::new(
-                ErrorKind::MYSQL_NO_DATA,
-                "not found app_client_key record",
-            ))
-        }
-    };
-    let dec_data = SM2::decrypt(&data[32..].to_vec(), &private_key.clone().into_bytes())?;
+    peer.check_serialid(&id)?;
+    let (key_version, ciphertext) = split_key_version(&data[32..])?;
+    let (key, private_key, dec_data) = decrypt_first(&id, key_version, ciphertext).await?;
+    let app_id = key.app_id;
+    peer.check_app(app_id)?;
     let token = ssl::create_token();
     let random_a = dec_data[0..32].to_vec();
     let mac = dec_data[32..].to_vec();
     let random_b: Vec<u8> = ssl::client_random(32);
     // query ca cert chain
-    let mut cert = match App::get(app_id)? {
-        Some(app) => app.certs.unwrap(),
-        None => return Err(Error::new(ErrorKind::MYSQL_NO_DATA, "not found app record")),
+    // 当前证书库没有有效证书时使用轮换前的证书库，x509 format der
+    let (random_private_key, cert) = match keycache::app(app_id).await? {
+        Some(app) => certpool::select(&app)?,
+        None => return Err(Error::new(ErrorKind::APP_NOT_FOUND, "not found app record")),
     };
-    let random_private_key = utils::prikey_from_pkcs12(cert.as_slice(), "123456")?;
-    // x509 format der
-    cert = utils::get_random_x509(cert.as_slice(), "123456")?;
     // write cache service
     Session::init(
         &token,
+        app_id,
+        id.as_str(),
         &random_a,
         &random_b,
         &mac,
         &random_private_key,
         &data_hash,
     )
-    .set()?;
+    .with_key_version(key.key_version)
+    .set()
+    .await?;
 
     let mut no_sign_data = Vec::new();
     no_sign_data.extend(&random_b);
@@ -69,11 +81,76 @@ pub fn tunnel_first(data: &Vec<u8>) -> error::Result<(Vec<u8>, Vec<u8>)> {
     Ok((sign_data, token))
 }
 
+/*
+   第一个请求唯一标识之后的数据
+   旧格式: SM2 密文
+   新格式: 0x00 | 密钥版本(4) | SM2 密文
+   SM2 密文为 DER 编码，首字节不会是 0x00
+*/
+fn split_key_version(data: &[u8]) -> error::Result<(Option<u32>, &[u8])> {
+    if data[0] != KEY_VERSION_TAG {
+        return Ok((None, data));
+    }
+    if data.len() <= 5 {
+        return Err(Error::new(
+            ErrorKind::DATA_INVALID,
+            "first request too short",
+        ));
+    }
+    Ok((Some(utils::u8_array_to_u32(&data[1..5])), &data[5..]))
+}
+
+/*
+   使用客户端指定的密钥版本解密，未指定时依次尝试可用的版本，新版本优先
+   轮换窗口内旧版本仍然可用，过期的版本不再接受，吊销名单中的客户端和已吊销的版本直接拒绝
+*/
+async fn decrypt_first(
+    serialid: &str,
+    key_version: Option<u32>,
+    ciphertext: &[u8],
+) -> error::Result<(AppClientKey, String, Vec<u8>)> {
+    if denylist::contains(serialid) {
+        return Err(revoked());
+    }
+    let now = Utc::now().timestamp();
+    let keys = keycache::client_key_versions(serialid).await?;
+    if !keys.is_empty() && keys.iter().all(|key| key.is_revoked()) {
+        return Err(revoked());
+    }
+    let keys: Vec<AppClientKey> = keys
+        .into_iter()
+        .filter(|key| !key.is_revoked())
+        .filter(|key| key_version.map_or(true, |version| key.key_version == version))
+        .filter(|key| key.accepted_at(now))
+        .collect();
+    if keys.is_empty() {
+        return Err(Error::new(
+            ErrorKind::KEY_NOT_FOUND,
+            "not found app_client_key record",
+        ));
+    }
+    let ciphertext = ciphertext.to_vec();
+    for key in keys {
+        let private_key = match &key.prikey {
+            Some(prikey) => envelope::open_string(prikey)?,
+            None => continue,
+        };
+        match SM2::decrypt(&ciphertext, &private_key.clone().into_bytes()) {
+            Ok(dec_data) if dec_data.len() >= 32 => return Ok((key, private_key, dec_data)),
+            _ => continue,
+        }
+    }
+    Err(Error::new(
+        ErrorKind::DATA_INVALID,
+        "first request decrypt failed",
+    ))
+}
+
 /*
    处理协商第二个请求
 */
-pub fn tunnel_second(entry: &mut DataEntry) -> error::Result<Vec<u8>> {
-    let mut session = Session::get(entry.token.clone())?;
+pub async fn tunnel_second(entry: &mut DataEntry) -> error::Result<Vec<u8>> {
+    let mut session = active_session(entry).await?;
     let data = SM2::decrypt(&entry.content, &session.prikey)?;
     let hash = SM3::hash(&entry.content);
     session.random_d = data;
@@ -100,7 +177,84 @@ pub fn tunnel_second(entry: &mut DataEntry) -> error::Result<Vec<u8>> {
     let session_encrypt_key = ssl::key(&key1);
     session.encrypt_key = session_encrypt_key.clone();
     entry.symmetric_key = session_encrypt_key.clone();
+    session.set().await?;
     let response = utils::vec_append(&session.request_hash, &hash);
 
     Ok(response)
 }
+
+/*
+   处理业务数据请求
+*/
+pub async fn tunnel_business(peer: &Peer, entry: &mut DataEntry) -> error::Result<Vec<u8>> {
+    let mut session = negotiated_session(entry).await?;
+    // 会话可能在其他连接上协商
+    peer.check_serialid(&session.serialid)?;
+    peer.check_app(session.app_id)?;
+    session.touch().await?;
+    let data = entry.decrypt();
+    let ctx = Context {
+        peer_addr: peer.addr,
+        client_subject: peer.client_subject.clone(),
+        hostname: peer.hostname.clone(),
+        session,
+    };
+    business::handler().handle(&ctx, data).await
+}
+
+/*
+   注销会话
+   请求数据为使用会话密钥加密的 TOKEN，用于证明持有会话密钥
+*/
+pub async fn tunnel_logout(entry: &mut DataEntry) -> error::Result<Vec<u8>> {
+    negotiated_session(entry).await?;
+    if entry.decrypt() != entry.token {
+        return Err(Error::new(ErrorKind::DATA_INVALID, "logout token mismatch"));
+    }
+    Session::delete(entry.token.clone()).await?;
+    Ok(vec![])
+}
+
+/*
+   读取会话，客户端已吊销时删除会话
+*/
+async fn active_session(entry: &DataEntry) -> error::Result<Session> {
+    let session = Session::get(entry.token.clone()).await?;
+    if denylist::contains(&session.serialid) {
+        Session::delete(entry.token.clone()).await?;
+        return Err(revoked());
+    }
+    Ok(session)
+}
+
+fn revoked() -> Error {
+    Error::new(ErrorKind::KEY_REVOKED, "client key revoked")
+}
+
+async fn negotiated_session(entry: &mut DataEntry) -> error::Result<Session> {
+    let session = active_session(entry).await?;
+    if session.encrypt_key.is_empty() {
+        return Err(Error::new(
+            ErrorKind::SESSION_KEY,
+            "session key not negotiated",
+        ));
+    }
+    entry.symmetric_key = session.encrypt_key.clone();
+    Ok(session)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_request_key_version() {
+        let (version, ciphertext) = split_key_version(&[0x30, 1, 2]).unwrap();
+        assert_eq!((version, ciphertext), (None, &[0x30, 1, 2][..]));
+
+        let (version, ciphertext) = split_key_version(&[0, 0, 0, 0, 2, 0x30, 1]).unwrap();
+        assert_eq!((version, ciphertext), (Some(2), &[0x30, 1][..]));
+
+        assert!(split_key_version(&[0, 0, 0, 0, 2]).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+/*
+   项目证书库签发
+   stserver ca init --cn "demo CA" --out ca.p12 [--days 3650] [--cert-out ca.pem]
+   stserver -c config.toml ca issue --ca ca.p12 [--count 10] [--days 365] [--cn demo] (--out pool.p12 | --app 1)
+   密码通过 --password-file/--password-env、--ca-password-file/--ca-password-env 指定，未指定时从终端输入
+   证书池中所有证书共用同一个私钥，由项目 CA 签发，握手时随机下发其中一张
+   生成的证书库私钥为池私钥，证书链只包含池证书，CA 证书单独分发给客户端
+*/
+
+use std::fs;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use openssl::{
+    asn1::{Asn1Integer, Asn1Time},
+    bn::{BigNum, MsbOption},
+    hash::MessageDigest,
+    nid::Nid,
+    pkcs12::Pkcs12,
+    pkey::{PKey, PKeyRef, Private},
+    stack::Stack,
+    x509::{
+        extension::{AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectKeyIdentifier},
+        X509Builder, X509Name, X509NameBuilder, X509,
+    },
+};
+
+use super::keys;
+use crate::{
+    error::{self, Error, ErrorKind},
+    sm::SM2,
+    store::mem,
+};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("ca")
+        .about("Issue SM2 certificate pools for app keystores")
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Create an app CA")
+                .arg(Arg::from_usage("--cn=<NAME> 'CA common name'"))
+                .arg(Arg::from_usage("--days=[DAYS] 'CA validity in days'").default_value("3650"))
+                .arg(Arg::from_usage("--out=<FILE> 'CA PKCS#12 output file'"))
+                .arg(Arg::from_usage(
+                    "--password-file=[FILE] 'Read the CA PKCS#12 password from a file'",
+                ))
+                .arg(Arg::from_usage(
+                    "--password-env=[NAME] 'Read the CA PKCS#12 password from an environment variable'",
+                ))
+                .arg(Arg::from_usage(
+                    "--cert-out=[FILE] 'Write CA certificate PEM to file'",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("issue")
+                .about("Issue a certificate pool keystore signed by the app CA")
+                .arg(Arg::from_usage("--ca=<FILE> 'CA PKCS#12 file'"))
+                .arg(Arg::from_usage(
+                    "--ca-password-file=[FILE] 'Read the CA PKCS#12 password from a file'",
+                ))
+                .arg(Arg::from_usage(
+                    "--ca-password-env=[NAME] 'Read the CA PKCS#12 password from an environment variable'",
+                ))
+                .arg(
+                    Arg::from_usage("--count=[COUNT] 'Number of certificates'").default_value("10"),
+                )
+                .arg(
+                    Arg::from_usage("--days=[DAYS] 'Certificate validity in days'")
+                        .default_value("365"),
+                )
+                .arg(
+                    Arg::from_usage("--cn=[NAME] 'Certificate common name'")
+                        .default_value("stserver"),
+                )
+                .arg(Arg::from_usage(
+                    "--password-file=[FILE] 'Read the keystore password from a file'",
+                ))
+                .arg(Arg::from_usage(
+                    "--password-env=[NAME] 'Read the keystore password from an environment variable'",
+                ))
+                .arg(Arg::from_usage("--out=[FILE] 'Keystore output file'").required_unless("app"))
+                .arg(Arg::from_usage(
+                    "--app=[ID] 'Import the keystore into this app'",
+                )),
+        )
+}
+
+pub async fn run(matches: &ArgMatches<'_>) -> error::Result<()> {
+    match matches.subcommand() {
+        ("init", Some(args)) => {
+            let days = keys::number_arg(args, "days")? as u32;
+            let passwd = super::password(args, "password", "CA password", true)?;
+            let authority = Authority::create(args.value_of("cn").unwrap(), days)?;
+            fs::write(args.value_of("out").unwrap(), authority.to_pkcs12(&passwd)?)?;
+            if let Some(path) = args.value_of("cert-out") {
+                fs::write(path, authority.cert.to_pem()?)?;
+            }
+            println!("created CA {}", args.value_of("cn").unwrap());
+            Ok(())
+        }
+        ("issue", Some(args)) => issue(args).await,
+        _ => Err(Error::new(
+            ErrorKind::CONFIG,
+            "missing ca subcommand, see --help",
+        )),
+    }
+}
+
+async fn issue(args: &ArgMatches<'_>) -> error::Result<()> {
+    let ca_passwd = super::password(args, "ca-password", "CA password", false)?;
+    let authority = Authority::load(&fs::read(args.value_of("ca").unwrap())?, &ca_passwd)?;
+    let count = keys::number_arg(args, "count")?;
+    let passwd = super::password(args, "password", "Keystore password", true)?;
+    let keystore = authority.issue_pool(
+        args.value_of("cn").unwrap(),
+        count,
+        keys::number_arg(args, "days")? as u32,
+        &passwd,
+    )?;
+    if let Some(path) = args.value_of("out") {
+        fs::write(path, &keystore)?;
+    }
+    if args.is_present("app") {
+        let app_id = keys::number_arg(args, "app")?;
+        let config = mem::CONFIG.lock()?.clone();
+        super::init_repository(&config).await?;
+        keys::save_keystore(app_id, &keystore, &passwd).await?;
+        println!("imported keystore for app {}", app_id);
+    }
+    println!("issued {} certificates", count);
+    Ok(())
+}
+
+pub struct Authority {
+    pub key: PKey<Private>,
+    pub cert: X509,
+}
+
+impl Authority {
+    // 自签名 CA
+    pub fn create(common_name: &str, days: u32) -> error::Result<Authority> {
+        let key = SM2::generate_pkey()?;
+        let name = subject(common_name)?;
+        let mut builder = certificate(&name, &key, days)?;
+        builder.set_issuer_name(&name)?;
+        builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .key_cert_sign()
+                .crl_sign()
+                .build()?,
+        )?;
+        let key_id = SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+        builder.append_extension(key_id)?;
+        builder.sign(&key, MessageDigest::sm3())?;
+        Ok(Authority {
+            key: key,
+            cert: builder.build(),
+        })
+    }
+
+    pub fn load(der: &[u8], passwd: &str) -> error::Result<Authority> {
+        let parsed = Pkcs12::from_der(der)?.parse2(passwd)?;
+        match (parsed.pkey, parsed.cert) {
+            (Some(key), Some(cert)) => {
+                SM2::alias_pkey(&key)?;
+                Ok(Authority {
+                    key: key,
+                    cert: cert,
+                })
+            }
+            _ => Err(Error::new(
+                ErrorKind::ERROR_STACK,
+                "CA PKCS#12 requires a private key and certificate",
+            )),
+        }
+    }
+
+    pub fn to_pkcs12(&self, passwd: &str) -> error::Result<Vec<u8>> {
+        let mut builder = Pkcs12::builder();
+        builder.name("ca").pkey(&self.key).cert(&self.cert);
+        Ok(builder.build2(passwd)?.to_der()?)
+    }
+
+    fn issue(&self, key: &PKeyRef<Private>, common_name: &str, days: u32) -> error::Result<X509> {
+        let mut builder = certificate(&subject(common_name)?, key, days)?;
+        builder.set_issuer_name(self.cert.subject_name())?;
+        builder.append_extension(BasicConstraints::new().critical().build()?)?;
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+        let key_id =
+            SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(&self.cert), None))?;
+        builder.append_extension(key_id)?;
+        let authority_key_id = AuthorityKeyIdentifier::new()
+            .keyid(true)
+            .build(&builder.x509v3_context(Some(&self.cert), None))?;
+        builder.append_extension(authority_key_id)?;
+        builder.sign(&self.key, MessageDigest::sm3())?;
+        Ok(builder.build())
+    }
+
+    /*
+       签发 count 张共用同一私钥的证书，打包为 tunnel_first 使用的 PKCS#12 证书库
+       私钥对应的第一张证书作为证书库主证书，全部证书放入证书链供随机选取
+    */
+    pub fn issue_pool(
+        &self,
+        common_name: &str,
+        count: usize,
+        days: u32,
+        passwd: &str,
+    ) -> error::Result<Vec<u8>> {
+        if count == 0 {
+            return Err(Error::new(
+                ErrorKind::CONFIG,
+                "certificate count must be greater than 0",
+            ));
+        }
+        let key = SM2::generate_pkey()?;
+        let mut chain = Stack::new()?;
+        for i in 0..count {
+            chain.push(self.issue(&key, &format!("{} {}", common_name, i + 1), days)?)?;
+        }
+        let cert = chain.get(0).unwrap().to_owned();
+        let mut builder = Pkcs12::builder();
+        builder.name(common_name).pkey(&key).cert(&cert).ca(chain);
+        Ok(builder.build2(passwd)?.to_der()?)
+    }
+}
+
+fn subject(common_name: &str) -> error::Result<X509Name> {
+    let mut builder = X509NameBuilder::new()?;
+    builder.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+    Ok(builder.build())
+}
+
+fn serial_number() -> error::Result<Asn1Integer> {
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+    Ok(serial.to_asn1_integer()?)
+}
+
+// 证书公共部分，有效期从当前时间开始
+fn certificate(name: &X509Name, key: &PKeyRef<Private>, days: u32) -> error::Result<X509Builder> {
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = serial_number()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(name)?;
+    builder.set_pubkey(key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(days)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn issue_pool() {
+        let authority = Authority::create("test CA", 30).unwrap();
+        let authority =
+            Authority::load(&authority.to_pkcs12("ca-pass").unwrap(), "ca-pass").unwrap();
+
+        let keystore = authority.issue_pool("test", 3, 7, "123456").unwrap();
+        let parsed = Pkcs12::from_der(&keystore)
+            .unwrap()
+            .parse2("123456")
+            .unwrap();
+        let pkey = parsed.pkey.unwrap();
+        let chain = parsed.ca.unwrap();
+        assert_eq!(chain.len(), 3);
+        for cert in chain.iter() {
+            assert!(cert.verify(&authority.key).unwrap());
+            assert!(cert.public_key().unwrap().public_eq(&pkey));
+        }
+
+        let cert = X509::from_der(&utils::get_random_x509(&keystore, "123456").unwrap()).unwrap();
+        assert_eq!(
+            cert.issuer_name().to_der().unwrap(),
+            authority.cert.subject_name().to_der().unwrap()
+        );
+        assert!(cert.not_after() < Asn1Time::days_from_now(8).unwrap());
+        assert!(utils::prikey_from_pkcs12(&keystore, "123456").is_ok());
+
+        assert!(authority.issue_pool("test", 0, 7, "123456").is_err());
+    }
+}
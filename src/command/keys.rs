@@ -0,0 +1,522 @@
+/*
+   密钥管理
+   stserver -c config.toml keys app-create --name demo [--description ...]
+   stserver -c config.toml keys app-list
+   stserver -c config.toml keys import --app 1 --file app.p12 [--password-file pass.txt | --password-env NAME]
+   stserver -c config.toml keys export --app 1 --file app.p12 [--show-password]
+   stserver -c config.toml keys certs --app 1
+   stserver -c config.toml keys retire --app 1
+   stserver -c config.toml keys gen --app 1 --serialid <32字节> --client-type 1 [--pubkey-out pub.pem]
+   stserver -c config.toml keys rotate --serialid <32字节> [--window-days 30] [--pubkey-out pub.pem]
+   stserver -c config.toml keys list [--app 1]
+   stserver -c config.toml keys revoke --serialid <32字节> --reason "lost device"
+   stserver -c config.toml keys delete --serialid <32字节>
+   stserver -c config.toml keys seal [--file clients/abc.key.pem]
+   私钥和证书库密码由主密钥加密后写入，修改后清除 redis 中的缓存
+   导入证书库时原证书库保留为上一版，确认新证书库可用后使用 retire 删除
+   revoke 保留密钥记录并加入吊销名单，拒绝握手并终止已有会话；delete 删除全部版本
+   seal 使用主密钥加密仓库中未加密的历史私钥和证书库密码；file 仓库只读，使用 --file 输出加密后的内容
+*/
+
+use std::fs;
+
+use chrono::Utc;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::{
+    error::{self, Error, ErrorKind},
+    sm::SM2,
+    store::{certpool, db::AppClientKey, denylist, envelope, keycache, mem, repository},
+    utils,
+};
+
+// 与握手第一个请求中的唯一标识长度一致
+const SERIALID_LEN: usize = 32;
+
+pub fn subcommand() -> App<'static, 'static> {
+    let app_arg = Arg::from_usage("--app=<ID> 'App id'");
+    SubCommand::with_name("keys")
+        .about("Manage apps, keystores and client keys")
+        .subcommand(
+            SubCommand::with_name("app-create")
+                .about("Create an app")
+                .arg(Arg::from_usage("--name=<NAME> 'App name'"))
+                .arg(Arg::from_usage("--description=[TEXT] 'App description'")),
+        )
+        .subcommand(SubCommand::with_name("app-list").about("List apps"))
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import a PKCS#12 keystore for an app")
+                .arg(app_arg.clone())
+                .arg(Arg::from_usage("--file=<FILE> 'PKCS#12 file'"))
+                .arg(Arg::from_usage(
+                    "--password-file=[FILE] 'Read the keystore password from a file'",
+                ))
+                .arg(Arg::from_usage(
+                    "--password-env=[NAME] 'Read the keystore password from an environment variable'",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export the PKCS#12 keystore of an app")
+                .arg(app_arg.clone())
+                .arg(Arg::from_usage("--file=<FILE> 'Output file'"))
+                .arg(Arg::from_usage(
+                    "--show-password 'Print the keystore password'",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("certs")
+                .about("Show certificate expiry of the app keystores")
+                .arg(app_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("retire")
+                .about("Remove the previous keystore of an app after rotation")
+                .arg(app_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("gen")
+                .about("Generate an SM2 key pair for a client")
+                .arg(app_arg.clone())
+                .arg(Arg::from_usage("--serialid=<ID> 'Client unique id'"))
+                .arg(Arg::from_usage(
+                    "--client-type=<TYPE> '0 ios, 1 android, 2 harmony'",
+                ))
+                .arg(Arg::from_usage(
+                    "--pubkey-out=[FILE] 'Write public key PEM to file'",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("rotate")
+                .about("Generate a new key version for a client")
+                .arg(Arg::from_usage("--serialid=<ID> 'Client unique id'"))
+                .arg(
+                    Arg::from_usage("--window-days=[DAYS] 'Days previous versions stay accepted'")
+                        .default_value("30"),
+                )
+                .arg(Arg::from_usage(
+                    "--pubkey-out=[FILE] 'Write public key PEM to file'",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List client keys")
+                .arg(Arg::from_usage("--app=[ID] 'Only list keys of this app'")),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke")
+                .about("Revoke all versions of a client key and kill its sessions")
+                .arg(Arg::from_usage("--serialid=<ID> 'Client unique id'"))
+                .arg(Arg::from_usage(
+                    "--reason=<TEXT> 'Revocation reason for audit'",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Delete all versions of a client key")
+                .arg(Arg::from_usage("--serialid=<ID> 'Client unique id'")),
+        )
+        .subcommand(
+            SubCommand::with_name("seal")
+                .about("Encrypt plaintext private keys and keystore passwords with the master key")
+                .arg(Arg::from_usage(
+                    "--file=[FILE] 'Print the sealed content of a file for the file repository'",
+                )),
+        )
+}
+
+pub async fn run(matches: &ArgMatches<'_>) -> error::Result<()> {
+    let config = mem::CONFIG.lock()?.clone();
+    super::init_repository(&config).await?;
+    match matches.subcommand() {
+        ("app-create", Some(args)) => app_create(args).await,
+        ("app-list", Some(_)) => app_list().await,
+        ("import", Some(args)) => import(args).await,
+        ("export", Some(args)) => export(args).await,
+        ("certs", Some(args)) => certs(args).await,
+        ("retire", Some(args)) => retire(args).await,
+        ("gen", Some(args)) => gen(args).await,
+        ("rotate", Some(args)) => rotate(args).await,
+        ("list", Some(args)) => list(args).await,
+        ("revoke", Some(args)) => revoke(args).await,
+        ("delete", Some(args)) => delete(args).await,
+        ("seal", Some(args)) => seal(args).await,
+        _ => Err(Error::new(
+            ErrorKind::CONFIG,
+            "missing keys subcommand, see --help",
+        )),
+    }
+}
+
+pub(super) fn number_arg(args: &ArgMatches<'_>, name: &str) -> error::Result<usize> {
+    match args.value_of(name).map(|value| value.parse::<usize>()) {
+        Some(Ok(value)) => Ok(value),
+        _ => Err(Error::new(
+            ErrorKind::CONFIG,
+            &format!("--{} must be a number", name),
+        )),
+    }
+}
+
+async fn app_create(args: &ArgMatches<'_>) -> error::Result<()> {
+    let id = repository::repository()?
+        .create_app(args.value_of("name").unwrap(), args.value_of("description"))
+        .await?;
+    println!("created app {}", id);
+    Ok(())
+}
+
+async fn app_list() -> error::Result<()> {
+    for app in repository::repository()?.apps().await? {
+        let keystore = match (&app.certs, &app.certs_passwd) {
+            (Some(_), Some(_)) => "keystore",
+            (Some(_), None) => "keystore without password",
+            _ => "no keystore",
+        };
+        println!("{:<6} {:<30} {}", app.id, app.name, keystore);
+    }
+    Ok(())
+}
+
+async fn import(args: &ArgMatches<'_>) -> error::Result<()> {
+    let app_id = number_arg(args, "app")?;
+    let certs = fs::read(args.value_of("file").unwrap())?;
+    let passwd = super::password(args, "password", "Keystore password", false)?;
+    save_keystore(app_id, &certs, &passwd).await?;
+    println!("imported keystore for app {}", app_id);
+    Ok(())
+}
+
+/*
+   保存项目证书库，保存前校验密码和证书链，与握手时使用同一套解析逻辑
+*/
+pub(super) async fn save_keystore(app_id: usize, certs: &[u8], passwd: &str) -> error::Result<()> {
+    utils::prikey_from_pkcs12(certs, passwd)?;
+    utils::get_random_x509(certs, passwd)?;
+
+    let repository = repository::repository()?;
+    if repository.app(app_id).await?.is_none() {
+        return Err(Error::new(ErrorKind::APP_NOT_FOUND, "not found app record"));
+    }
+    repository
+        .set_keystore(app_id, certs, &envelope::seal(passwd.as_bytes())?)
+        .await?;
+    invalidate_app(app_id).await;
+    Ok(())
+}
+
+/*
+   仓库写入成功后使缓存失效，失效失败只记录警告，缓存条目在 ttl 后过期
+*/
+async fn invalidate_app(app_id: usize) {
+    if let Err(err) = keycache::invalidate_app(app_id).await {
+        eprintln!(
+            "warning: app {} cache not invalidated, expires by ttl: {:?}",
+            app_id, err
+        );
+    }
+}
+
+async fn invalidate_client_key(serialid: &str) {
+    if let Err(err) = keycache::invalidate_client_key(serialid).await {
+        eprintln!(
+            "warning: {} cache not invalidated, expires by ttl: {:?}",
+            serialid, err
+        );
+    }
+}
+
+async fn export(args: &ArgMatches<'_>) -> error::Result<()> {
+    let app_id = number_arg(args, "app")?;
+    let app = match repository::repository()?.app(app_id).await? {
+        Some(app) => app,
+        None => return Err(Error::new(ErrorKind::APP_NOT_FOUND, "not found app record")),
+    };
+    let certs = match app.certs {
+        Some(certs) => certs,
+        None => return Err(Error::new(ErrorKind::KEY_NOT_FOUND, "app keystore not set")),
+    };
+    fs::write(args.value_of("file").unwrap(), &certs)?;
+    if args.is_present("show-password") {
+        match &app.certs_passwd {
+            Some(passwd) => println!("keystore password: {}", envelope::open_string(passwd)?),
+            None => println!("keystore password not set"),
+        }
+    }
+    Ok(())
+}
+
+async fn certs(args: &ArgMatches<'_>) -> error::Result<()> {
+    let app_id = number_arg(args, "app")?;
+    let app = match repository::repository()?.app(app_id).await? {
+        Some(app) => app,
+        None => return Err(Error::new(ErrorKind::APP_NOT_FOUND, "not found app record")),
+    };
+    for (label, certs, passwd) in certpool::keystores(&app) {
+        println!("{} keystore", label);
+        let passwd = match passwd {
+            Ok(passwd) => passwd,
+            Err(err) => {
+                println!("  password error: {:?}", err);
+                continue;
+            }
+        };
+        for expiry in utils::cert_expiries(certs, &passwd)? {
+            println!(
+                "  {:<30} {} ({} days)",
+                expiry.subject, expiry.not_after, expiry.days_left
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn retire(args: &ArgMatches<'_>) -> error::Result<()> {
+    let app_id = number_arg(args, "app")?;
+    repository::repository()?.retire_keystore(app_id).await?;
+    invalidate_app(app_id).await;
+    println!("retired previous keystore of app {}", app_id);
+    Ok(())
+}
+
+async fn gen(args: &ArgMatches<'_>) -> error::Result<()> {
+    let app_id = number_arg(args, "app")?;
+    let client_type = number_arg(args, "client-type")?;
+    let serialid = args.value_of("serialid").unwrap();
+    if serialid.len() != SERIALID_LEN {
+        return Err(Error::new(
+            ErrorKind::DATA_INVALID,
+            &format!("serialid must be {} bytes", SERIALID_LEN),
+        ));
+    }
+
+    let repository = repository::repository()?;
+    if repository.app(app_id).await?.is_none() {
+        return Err(Error::new(ErrorKind::APP_NOT_FOUND, "not found app record"));
+    }
+    if repository.client_key(serialid).await?.is_some() {
+        return Err(Error::new(
+            ErrorKind::DATA_INVALID,
+            "serialid already has a key",
+        ));
+    }
+
+    save_new_key(
+        args,
+        AppClientKey {
+            app_id: app_id,
+            client_type: client_type,
+            serialid: Some(serialid.to_string()),
+            pubkey: None,
+            prikey: None,
+            key_version: 1,
+            expires_at: None,
+            status: 0,
+            revoked_reason: None,
+            revoked_at: None,
+        },
+    )
+    .await
+}
+
+/*
+   生成新版本密钥，旧版本在轮换窗口内仍然可用
+   客户端更新公钥前可以继续使用旧版本握手
+*/
+async fn rotate(args: &ArgMatches<'_>) -> error::Result<()> {
+    let serialid = args.value_of("serialid").unwrap();
+    let window_days = number_arg(args, "window-days")? as i64;
+    let repository = repository::repository()?;
+    let mut key = match repository.client_key(serialid).await? {
+        Some(key) => key,
+        None => {
+            return Err(Error::new(
+                ErrorKind::KEY_NOT_FOUND,
+                "not found app_client_key record",
+            ))
+        }
+    };
+    if key.is_revoked() {
+        return Err(Error::new(ErrorKind::KEY_REVOKED, "client key revoked"));
+    }
+    let expires_at = window_days
+        .checked_mul(24 * 3600)
+        .and_then(|secs| Utc::now().timestamp().checked_add(secs))
+        .ok_or_else(|| Error::new(ErrorKind::CONFIG, "--window-days is too large"))?;
+    repository.expire_client_key(serialid, expires_at).await?;
+    key.key_version += 1;
+    key.expires_at = None;
+    let key_version = key.key_version;
+    save_new_key(args, key).await?;
+    println!(
+        "rotated {} to version {}, previous versions accepted until {}",
+        serialid,
+        key_version,
+        super::local_time(expires_at)
+    );
+    Ok(())
+}
+
+// 生成密钥对写入 key，输出公钥
+async fn save_new_key(args: &ArgMatches<'_>, mut key: AppClientKey) -> error::Result<()> {
+    let (private_key, public_key) = SM2::generate_keypair()?;
+    let public_key = String::from_utf8(public_key)?;
+    key.pubkey = Some(public_key.clone());
+    key.prikey = Some(envelope::seal(&private_key)?);
+    repository::repository()?.insert_client_key(&key).await?;
+    invalidate_client_key(key.serialid.as_deref().unwrap_or_default()).await;
+
+    match args.value_of("pubkey-out") {
+        Some(path) => fs::write(path, &public_key)?,
+        None => print!("{}", public_key),
+    }
+    Ok(())
+}
+
+async fn list(args: &ArgMatches<'_>) -> error::Result<()> {
+    let app_id = match args.value_of("app") {
+        Some(_) => Some(number_arg(args, "app")?),
+        None => None,
+    };
+    for key in repository::repository()?.client_keys(app_id).await? {
+        let prikey = match &key.prikey {
+            Some(prikey) if envelope::is_sealed(prikey) => "sealed",
+            Some(_) => "plaintext",
+            None => "none",
+        };
+        let expires = match key.expires_at {
+            Some(expires_at) => format!(" expires {}", super::local_time(expires_at)),
+            None => String::new(),
+        };
+        let revoked = match (key.is_revoked(), key.revoked_at) {
+            (true, Some(revoked_at)) => format!(
+                " revoked {} ({})",
+                super::local_time(revoked_at),
+                key.revoked_reason.as_deref().unwrap_or_default()
+            ),
+            (true, None) => " revoked".to_string(),
+            (false, _) => String::new(),
+        };
+        println!(
+            "{:<34} v{:<3} app {:<6} type {:<3} prikey {}{}{}",
+            key.serialid.as_deref().unwrap_or_default(),
+            key.key_version,
+            key.app_id,
+            key.client_type,
+            prikey,
+            expires,
+            revoked
+        );
+    }
+    Ok(())
+}
+
+/*
+   先在密钥仓库标记吊销再加入吊销名单，重复执行不影响结果
+*/
+async fn revoke(args: &ArgMatches<'_>) -> error::Result<()> {
+    let serialid = args.value_of("serialid").unwrap();
+    let reason = args.value_of("reason").unwrap();
+    if !repository::repository()?
+        .revoke_client_key(serialid, reason, Utc::now().timestamp())
+        .await?
+    {
+        return Err(Error::new(
+            ErrorKind::KEY_NOT_FOUND,
+            "not found app_client_key record",
+        ));
+    }
+    invalidate_client_key(serialid).await;
+    // 其他节点通过 redis 集合终止已有会话，写入失败时需要重新执行
+    if let Err(err) = denylist::add(serialid).await {
+        println!(
+            "{} revoked in repository but denylist not updated, retry",
+            serialid
+        );
+        return Err(err);
+    }
+    println!("revoked {}: {}", serialid, reason);
+    Ok(())
+}
+
+async fn delete(args: &ArgMatches<'_>) -> error::Result<()> {
+    let serialid = args.value_of("serialid").unwrap();
+    if !repository::repository()?
+        .delete_client_key(serialid)
+        .await?
+    {
+        return Err(Error::new(
+            ErrorKind::KEY_NOT_FOUND,
+            "not found app_client_key record",
+        ));
+    }
+    invalidate_client_key(serialid).await;
+    // 密钥记录已删除，同时移出吊销名单，否则重新创建的同名 serialid 仍被拒绝
+    if let Err(err) = denylist::remove(serialid).await {
+        println!(
+            "{} deleted in repository but denylist not updated, retry",
+            serialid
+        );
+        return Err(err);
+    }
+    println!("deleted {}", serialid);
+    Ok(())
+}
+
+async fn seal(args: &ArgMatches<'_>) -> error::Result<()> {
+    if let Some(path) = args.value_of("file") {
+        let data = fs::read(path)?;
+        if envelope::is_sealed(&String::from_utf8_lossy(&data)) {
+            return Err(Error::new(ErrorKind::CONFIG, "file is already sealed"));
+        }
+        println!("{}", envelope::seal(&data)?);
+        return Ok(());
+    }
+
+    let repository = repository::repository()?;
+    let mut apps = 0;
+    for app in repository.apps().await? {
+        let certs_passwd = seal_plaintext(&app.certs_passwd)?;
+        let prev_certs_passwd = seal_plaintext(&app.prev_certs_passwd)?;
+        if certs_passwd.is_none() && prev_certs_passwd.is_none() {
+            continue;
+        }
+        repository
+            .update_keystore_passwd(
+                app.id,
+                certs_passwd.as_deref().or(app.certs_passwd.as_deref()),
+                prev_certs_passwd
+                    .as_deref()
+                    .or(app.prev_certs_passwd.as_deref()),
+            )
+            .await?;
+        invalidate_app(app.id).await;
+        apps += 1;
+    }
+
+    let mut keys = 0;
+    for key in repository.client_keys(None).await? {
+        let serialid = key.serialid.as_deref().unwrap_or_default();
+        if let Some(prikey) = seal_plaintext(&key.prikey)? {
+            repository
+                .update_client_prikey(serialid, key.key_version, &prikey)
+                .await?;
+            invalidate_client_key(serialid).await;
+            keys += 1;
+        }
+    }
+    println!(
+        "sealed {} app keystore passwords and {} client keys",
+        apps, keys
+    );
+    Ok(())
+}
+
+// 未加密的值返回加密后的内容，已加密或者为空返回 None
+fn seal_plaintext(value: &Option<String>) -> error::Result<Option<String>> {
+    match value {
+        Some(value) if !envelope::is_sealed(value) => Ok(Some(envelope::seal(value.as_bytes())?)),
+        _ => Ok(None),
+    }
+}
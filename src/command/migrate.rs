@@ -0,0 +1,44 @@
+/*
+   stserver -c config.toml migrate up      执行未执行的迁移
+   stserver -c config.toml migrate status  查看迁移状态
+*/
+
+use clap::{App, ArgMatches, SubCommand};
+
+use crate::{error, store::mem, store::migrate};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("migrate")
+        .about("Manage database schema migrations")
+        .subcommand(SubCommand::with_name("up").about("Apply pending migrations"))
+        .subcommand(SubCommand::with_name("status").about("Show applied and pending migrations"))
+}
+
+pub async fn run(matches: &ArgMatches<'_>) -> error::Result<()> {
+    let config = mem::CONFIG.lock()?.clone();
+    match matches.subcommand_name() {
+        Some("status") => {
+            for (migration, applied) in migrate::status(&config).await? {
+                let state = match applied {
+                    Some(applied) => {
+                        format!("applied at {}", super::local_time(applied.applied_at))
+                    }
+                    None => "pending".to_string(),
+                };
+                println!("{:04} {:<20} {}", migration.version, migration.name, state);
+            }
+        }
+        _ => {
+            let versions = migrate::up(&config).await?;
+            match versions.is_empty() {
+                true => println!("schema is up to date"),
+                false => {
+                    for version in versions {
+                        println!("applied migration {:04}", version);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
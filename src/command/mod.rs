@@ -0,0 +1,112 @@
+/*
+   管理子命令，执行完成后退出，不启动服务
+   stserver -c config.toml <子命令>
+*/
+
+pub mod ca;
+pub mod keys;
+pub mod migrate;
+
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+};
+
+use chrono::{Local, TimeZone};
+use clap::ArgMatches;
+
+use crate::{
+    config::Config,
+    error::{self, Error, ErrorKind},
+    store::{keycache, mysql_pool, redis_pool, repository},
+};
+
+/*
+   初始化密钥仓库和缓存
+   redis 用于修改后清除缓存，不可用时只打印提示，由缓存过期时间兜底
+*/
+pub async fn init_repository(config: &Config) -> error::Result<()> {
+    let uses_mysql = config
+        .repository
+        .as_ref()
+        .map_or(true, |repository| repository.backend == "mysql");
+    if let (true, Some(mysql)) = (uses_mysql, &config.mysql) {
+        mysql_pool::init(mysql).await?;
+    }
+    if let Some(redis) = &config.redis {
+        if let Err(err) = redis_pool::init(redis).await {
+            println!("redis unavailable, cache will expire by ttl: {:?}", err);
+        }
+    }
+    repository::init(config).await?;
+    keycache::init(config);
+    Ok(())
+}
+
+/*
+   密码参数，不接受命令行明文，避免出现在进程列表和 shell 历史中
+   --<name>-file 读取文件第一行，--<name>-env 读取环境变量，都未指定时从终端输入
+*/
+pub(crate) fn password(
+    args: &ArgMatches<'_>,
+    name: &str,
+    label: &str,
+    confirm: bool,
+) -> error::Result<String> {
+    if let Some(path) = args.value_of(format!("{}-file", name)) {
+        let content = fs::read_to_string(path)?;
+        return Ok(content.lines().next().unwrap_or_default().to_string());
+    }
+    if let Some(var) = args.value_of(format!("{}-env", name)) {
+        return env::var(var)
+            .map_err(|_| Error::new(ErrorKind::CONFIG, &format!("password env {} not set", var)));
+    }
+    let passwd = prompt(&format!("{}: ", label))?;
+    if confirm && prompt(&format!("Confirm {}: ", label.to_lowercase()))? != passwd {
+        return Err(Error::new(ErrorKind::CONFIG, "passwords do not match"));
+    }
+    Ok(passwd)
+}
+
+// 终端输入时关闭回显，标准输入不是终端时直接读取一行
+fn prompt(label: &str) -> error::Result<String> {
+    eprint!("{}", label);
+    io::stderr().flush()?;
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    let tty = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0;
+    if tty {
+        let mut silent = termios;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent) };
+    }
+    let mut line = String::new();
+    let res = io::stdin().lock().read_line(&mut line);
+    if tty {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+        eprintln!();
+    }
+    res?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+// 本地时间，超出范围的时间戳不会 panic
+pub(crate) fn local_time(secs: i64) -> String {
+    match Local.timestamp_opt(secs, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => format!("invalid timestamp {}", secs),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn out_of_range_time() {
+        assert_eq!(
+            local_time(i64::MAX),
+            format!("invalid timestamp {}", i64::MAX)
+        );
+        assert_eq!(local_time(0).len(), "1970-01-01 00:00:00".len());
+    }
+}
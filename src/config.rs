@@ -8,32 +8,444 @@ use std::{
 use crate::error;
 use crate::store::mem;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct Config {
     pub app: Option<App>,
     pub redis: Option<Redis>,
     pub mysql: Option<Mysql>,
+    pub gateway: Option<Gateway>,
+    pub session: Option<Session>,
+    pub cache: Option<Cache>,
+    pub repository: Option<Repository>,
+    pub master_key: Option<MasterKey>,
+    pub cert_pool: Option<CertPool>,
+    pub denylist: Option<Denylist>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct App {
     pub tls_cert: String,
     pub tls_key: String,
     pub addr: String,
+    // 单个报文最大长度，超出的报文丢弃
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    // 单个连接同时处理的报文数，达到上限后暂停读取
+    #[serde(default = "default_max_inflight_frames")]
+    pub max_inflight_frames: usize,
+    // 客户端证书校验 none/optional/required，optional 时未提供证书的客户端仍可连接
+    #[serde(default = "default_client_auth")]
+    pub client_auth: String,
+    // 校验客户端证书的 CA 证书 PEM 文件，可包含多张
+    pub client_ca: Option<String>,
+    // client_auth 不为 none 时要求证书 CN 与握手使用的 serialid 一致
+    // optional 时未提供证书的客户端也会被拒绝，允许匿名客户端需要关闭
+    #[serde(default = "default_client_cert_bind_serialid")]
+    pub client_cert_bind_serialid: bool,
+    // 检查配置文件和 TLS 证书私钥是否修改的间隔，0 表示只在收到 SIGHUP 时重新加载
+    #[serde(default)]
+    pub watch_interval_secs: u64,
+    // 按 SNI 主机名选择的证书，未匹配时使用 tls_cert/tls_key
+    #[serde(default)]
+    pub sni: Vec<Sni>,
+    // 未匹配 [[app.sni]] 主机名的连接允许的项目，为空时允许未被主机名限定的项目
+    #[serde(default)]
+    pub default_app_ids: Vec<usize>,
 }
 
-#[derive(Deserialize)]
+/*
+   [[app.sni]] 单个域名的证书
+   app_ids 非空时该域名上只允许这些项目握手，这些项目也不能在其他域名上握手
+   为空时不允许其他域名限定的项目
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Sni {
+    pub hostname: String,
+    pub tls_cert: String,
+    pub tls_key: String,
+    #[serde(default)]
+    pub app_ids: Vec<usize>,
+}
+
+fn default_max_frame_size() -> usize {
+    1024 * 1024
+}
+
+fn default_max_inflight_frames() -> usize {
+    16
+}
+
+fn default_client_auth() -> String {
+    "none".to_string()
+}
+
+fn default_client_cert_bind_serialid() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct Redis {
+    // 部署模式 single/cluster/sentinel，默认 single
+    #[serde(default = "default_redis_mode")]
+    pub mode: String,
+    // single 模式地址，cluster/sentinel 模式未配置 nodes 时作为唯一种子节点
     pub url: String,
+    // cluster 种子节点或者 sentinel 节点列表
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    // sentinel 监控的 master 名称
+    #[serde(default)]
+    pub sentinel_master: String,
+    // redis 6 ACL 用户名，为空时仅使用密码认证
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
     pub auth_passwd: String,
+    // 使用 TLS 连接，地址为 rediss:// 时同样启用
+    #[serde(default)]
+    pub tls: bool,
+    // 不校验服务端证书，仅用于测试环境
+    #[serde(default)]
+    pub tls_insecure: bool,
+    // 连接数，连接断开后自动重连
+    #[serde(default = "default_redis_pool_size")]
+    pub pool_size: usize,
+    #[serde(default = "default_redis_connect_timeout")]
+    pub connect_timeout_secs: u64,
+    #[serde(default = "default_redis_response_timeout")]
+    pub response_timeout_secs: u64,
+}
+
+fn default_redis_mode() -> String {
+    "single".to_string()
+}
+
+fn default_redis_pool_size() -> usize {
+    4
+}
+
+fn default_redis_connect_timeout() -> u64 {
+    5
+}
+
+fn default_redis_response_timeout() -> u64 {
+    3
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct Mysql {
     pub host: String,
     pub port: i32,
     pub user: String,
     pub passwd: String,
+    #[serde(default = "default_mysql_db_name")]
+    pub db_name: String,
+    // 连接池最小和最大连接数
+    #[serde(default = "default_mysql_pool_min")]
+    pub pool_min: usize,
+    #[serde(default = "default_mysql_pool_max")]
+    pub pool_max: usize,
+    #[serde(default = "default_mysql_connect_timeout")]
+    pub connect_timeout_secs: u64,
+    // 单次查询超时，包括等待空闲连接的时间
+    #[serde(default = "default_mysql_query_timeout")]
+    pub query_timeout_secs: u64,
+}
+
+fn default_mysql_db_name() -> String {
+    "stserver".to_string()
+}
+
+fn default_mysql_pool_min() -> usize {
+    1
+}
+
+fn default_mysql_pool_max() -> usize {
+    10
+}
+
+fn default_mysql_connect_timeout() -> u64 {
+    5
+}
+
+fn default_mysql_query_timeout() -> u64 {
+    5
+}
+
+/*
+   会话超时，单位秒，0表示不限制
+   absolute_timeout_secs 从协商开始计算
+   idle_timeout_secs 从最后一次业务请求开始计算
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Session {
+    // 会话存储 memory/redis
+    #[serde(default = "default_session_store")]
+    pub store: String,
+    #[serde(default = "default_session_absolute_timeout")]
+    pub absolute_timeout_secs: u64,
+    #[serde(default = "default_session_idle_timeout")]
+    pub idle_timeout_secs: u64,
+    // 过期后保留多久用于返回会话过期错误
+    #[serde(default = "default_session_expired_retention")]
+    pub expired_retention_secs: u64,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            store: default_session_store(),
+            absolute_timeout_secs: default_session_absolute_timeout(),
+            idle_timeout_secs: default_session_idle_timeout(),
+            expired_retention_secs: default_session_expired_retention(),
+        }
+    }
+}
+
+fn default_session_store() -> String {
+    "redis".to_string()
+}
+
+fn default_session_absolute_timeout() -> u64 {
+    24 * 3600
+}
+
+fn default_session_idle_timeout() -> u64 {
+    30 * 60
+}
+
+fn default_session_expired_retention() -> u64 {
+    3600
+}
+
+/*
+   密钥材料静态加密的主密钥，SM4 16 字节十六进制
+   env 从环境变量读取，file 从文件读取，都配置时优先 env
+   allow_plaintext 迁移期间接受未加密的历史数据，使用 keys seal 加密后关闭
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct MasterKey {
+    pub env: Option<String>,
+    pub file: Option<String>,
+    #[serde(default)]
+    pub allow_plaintext: bool,
+}
+
+/*
+   项目和客户端密钥来源
+   backend: mysql 使用 [mysql] 配置 / postgres 使用 url / sqlite 使用 path / file 使用 dir
+   未配置时使用 mysql
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Repository {
+    #[serde(default = "default_repository_backend")]
+    pub backend: String,
+    // postgres 连接串，如 host=127.0.0.1 user=stserver password=xxx dbname=stserver
+    pub url: Option<String>,
+    // sqlite 数据库文件
+    pub path: Option<String>,
+    // 密钥目录，包含 keys.toml 和其引用的 PEM/PKCS#12 文件
+    pub dir: Option<String>,
+    #[serde(default = "default_repository_pool_size")]
+    pub pool_size: usize,
+    #[serde(default = "default_repository_connect_timeout")]
+    pub connect_timeout_secs: u64,
+}
+
+fn default_repository_backend() -> String {
+    "mysql".to_string()
+}
+
+fn default_repository_pool_size() -> usize {
+    4
+}
+
+fn default_repository_connect_timeout() -> u64 {
+    5
+}
+
+/*
+   项目和客户端密钥读缓存，进程内 LRU -> redis -> mysql
+   其他节点修改数据后本地缓存最多保留 local_ttl_secs
+   redis_ttl_secs 为 0 时不使用 redis 层
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Cache {
+    #[serde(default = "default_cache_capacity")]
+    pub capacity: usize,
+    #[serde(default = "default_cache_local_ttl")]
+    pub local_ttl_secs: u64,
+    #[serde(default = "default_cache_redis_ttl")]
+    pub redis_ttl_secs: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            capacity: default_cache_capacity(),
+            local_ttl_secs: default_cache_local_ttl(),
+            redis_ttl_secs: default_cache_redis_ttl(),
+        }
+    }
+}
+
+fn default_cache_capacity() -> usize {
+    1024
+}
+
+fn default_cache_local_ttl() -> u64 {
+    60
+}
+
+fn default_cache_redis_ttl() -> u64 {
+    600
+}
+
+/*
+   项目证书池有效期检查
+   剩余天数小于 warn_days 的证书定时打印告警，check_interval_secs 为 0 时不检查
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct CertPool {
+    #[serde(default = "default_cert_pool_warn_days")]
+    pub warn_days: i32,
+    #[serde(default = "default_cert_pool_check_interval")]
+    pub check_interval_secs: u64,
+}
+
+impl Default for CertPool {
+    fn default() -> Self {
+        CertPool {
+            warn_days: default_cert_pool_warn_days(),
+            check_interval_secs: default_cert_pool_check_interval(),
+        }
+    }
+}
+
+fn default_cert_pool_warn_days() -> i32 {
+    30
+}
+
+fn default_cert_pool_check_interval() -> u64 {
+    3600
+}
+
+/*
+   吊销名单同步间隔，其他节点吊销的客户端最迟在该间隔后被拒绝，0 表示不同步
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Denylist {
+    #[serde(default = "default_denylist_sync_interval")]
+    pub sync_interval_secs: u64,
+}
+
+impl Default for Denylist {
+    fn default() -> Self {
+        Denylist {
+            sync_interval_secs: default_denylist_sync_interval(),
+        }
+    }
+}
+
+fn default_denylist_sync_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Gateway {
+    // 上游请求超时时间，单位秒
+    #[serde(default = "default_gateway_timeout")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub health_check: HealthCheck,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+}
+
+fn default_gateway_timeout() -> u64 {
+    30
+}
+
+/*
+   上游主机健康检查
+   配置 path 后启用主动检查，被动检查始终启用
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct HealthCheck {
+    pub path: Option<String>,
+    #[serde(default = "default_health_interval")]
+    pub interval_secs: u64,
+    #[serde(default = "default_health_timeout")]
+    pub timeout_secs: u64,
+    // 连续失败多少次后摘除
+    #[serde(default = "default_health_failure_threshold")]
+    pub failure_threshold: u32,
+    // 摘除后多久重新加入
+    #[serde(default = "default_health_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            path: None,
+            interval_secs: default_health_interval(),
+            timeout_secs: default_health_timeout(),
+            failure_threshold: default_health_failure_threshold(),
+            cooldown_secs: default_health_cooldown(),
+        }
+    }
+}
+
+/*
+   接口限流
+   backend: local 单节点内存 / redis 多节点共享
+   per_client: 是否按客户端唯一标识分别限流
+   fail_open: 限流服务异常(如 redis 不可用)时是否放行，默认放行，此时所有节点的接口限流都失效
+   设为 false 时拒绝请求并返回限流错误
+*/
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct RateLimit {
+    #[serde(default = "default_rate_limit_backend")]
+    pub backend: String,
+    #[serde(default)]
+    pub per_client: bool,
+    #[serde(default = "default_rate_limit_fail_open")]
+    pub fail_open: bool,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            backend: default_rate_limit_backend(),
+            per_client: false,
+            fail_open: default_rate_limit_fail_open(),
+        }
+    }
+}
+
+fn default_rate_limit_fail_open() -> bool {
+    true
+}
+
+fn default_rate_limit_backend() -> String {
+    "local".to_string()
+}
+
+fn default_health_interval() -> u64 {
+    10
+}
+
+fn default_health_timeout() -> u64 {
+    3
+}
+
+fn default_health_failure_threshold() -> u32 {
+    3
+}
+
+fn default_health_cooldown() -> u64 {
+    30
 }
 
 impl Config {
@@ -42,16 +454,49 @@ impl Config {
             app: None,
             redis: None,
             mysql: None,
+            gateway: None,
+            session: None,
+            cache: None,
+            repository: None,
+            master_key: None,
+            cert_pool: None,
+            denylist: None,
         }
     }
 }
 
-pub fn parse_config(path: &str) -> error::Result<()> {
+pub fn load(path: &str) -> error::Result<Config> {
     let mut file = File::open(path)?;
     let file_size = file.metadata()?.len();
     let mut buffer: Vec<u8> = Vec::with_capacity(file_size as usize);
     file.read_to_end(&mut buffer)?;
-    let config: Config = toml::from_slice(buffer.as_slice())?;
+    Ok(toml::from_slice(buffer.as_slice())?)
+}
+
+/*
+   重新加载时只替换 [app]，其他配置在启动时用于初始化存储、网关等，返回有修改、需要重启生效的配置
+*/
+pub fn restart_sections(old: &Config, new: &Config) -> Vec<&'static str> {
+    let sections = [
+        ("redis", old.redis == new.redis),
+        ("mysql", old.mysql == new.mysql),
+        ("gateway", old.gateway == new.gateway),
+        ("session", old.session == new.session),
+        ("cache", old.cache == new.cache),
+        ("repository", old.repository == new.repository),
+        ("master_key", old.master_key == new.master_key),
+        ("cert_pool", old.cert_pool == new.cert_pool),
+        ("denylist", old.denylist == new.denylist),
+    ];
+    sections
+        .iter()
+        .filter(|(_, same)| !same)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+pub fn parse_config(path: &str) -> error::Result<()> {
+    let config = load(path)?;
     let mut config_value = mem::CONFIG.lock()?;
     *config_value = config;
     Ok(())
@@ -14,6 +14,8 @@ use crate::config::Config;
 pub struct Error {
     code: ErrorKind,
     msg: String,
+    // 建议客户端重试的间隔秒数
+    retry_after: Option<u32>,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -23,19 +25,40 @@ impl Error {
         Error {
             code: kind,
             msg: String::from(msg),
+            retry_after: None,
         }
     }
 
+    pub fn with_retry_after(mut self, secs: u32) -> Error {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.code
+    }
+
+    pub fn code(&self) -> u16 {
+        self.code.code()
+    }
+
     pub fn mysql_convert(err: mysql::Error) -> Error {
-        Error {
-            code: ErrorKind::MYSQL,
-            msg: err.to_string(),
-        }
+        Error::from(err)
     }
 
-    // 转为字节流
+    /*
+       转为字节流，作为 data_type 0 和 5 报文的数据段
+       错误码(2) | 重试间隔秒数(4，0表示不需要重试) | 错误信息长度(2) | 错误信息
+       错误信息为错误码对应的固定信息，不包含 msg
+    */
     pub fn to_vec(&self) -> Vec<u8> {
-        todo!()
+        let msg = self.code.public_message().as_bytes();
+        let mut res = Vec::with_capacity(8 + msg.len());
+        res.extend_from_slice(&self.code().to_be_bytes());
+        res.extend_from_slice(&self.retry_after.unwrap_or(0).to_be_bytes());
+        res.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        res.extend_from_slice(msg);
+        res
     }
 }
 
@@ -70,6 +93,99 @@ pub enum ErrorKind {
     REDIS,
     SERDE_JSON,
     ERROR_STACK,
+    SESSION_KEY,
+    CONFIG,
+    SESSION_NOT_FOUND,
+    SESSION_EXPIRED,
+    KEY_NOT_FOUND,
+    KEY_DECRYPT,
+    CERT_EXPIRED,
+    KEY_REVOKED,
+    CLIENT_CERT,
+    APP_NOT_ALLOWED,
+    APP_NOT_FOUND,
+    RATE_LIMITED,
+    POSTGRES,
+    SQLITE,
+}
+
+/*
+   返回给客户端的错误码，已发布的取值不能修改
+   1xxx 报文错误
+   2xxx 会话错误
+   3xxx 密钥错误
+   4xxx 业务请求错误
+   5xxx 服务端内部错误
+*/
+impl ErrorKind {
+    pub fn code(&self) -> u16 {
+        match self {
+            ErrorKind::DATA_INVALID => 1001,
+            ErrorKind::DATA_PACK => 1002,
+            ErrorKind::DATA_TYPE => 1003,
+            ErrorKind::DATA_UNPACK_OLDDATA_NOMATCH => 1004,
+            ErrorKind::DATA_IO => 1005,
+            ErrorKind::OS_FromUtf8Error => 1006,
+            ErrorKind::SESSION_NOT_FOUND => 2001,
+            ErrorKind::SESSION_EXPIRED => 2002,
+            ErrorKind::SESSION_KEY => 2003,
+            ErrorKind::KEY_NOT_FOUND => 3001,
+            ErrorKind::SM2_EVP_PKEY => 3002,
+            ErrorKind::ERROR_STACK => 3003,
+            ErrorKind::KEY_DECRYPT => 3004,
+            ErrorKind::CERT_EXPIRED => 3005,
+            ErrorKind::KEY_REVOKED => 3006,
+            ErrorKind::CLIENT_CERT => 3007,
+            ErrorKind::APP_NOT_ALLOWED => 3008,
+            ErrorKind::APP_NOT_FOUND => 3009,
+            ErrorKind::RATE_LIMITED => 4001,
+            ErrorKind::MYSQL => 5001,
+            ErrorKind::MYSQL_NO_DATA => 5002,
+            ErrorKind::REDIS => 5003,
+            ErrorKind::SERDE_JSON => 5004,
+            ErrorKind::TOML_DESERIALIZE => 5005,
+            ErrorKind::OS_POISONERROR => 5006,
+            ErrorKind::CONFIG => 5007,
+            ErrorKind::POSTGRES => 5008,
+            ErrorKind::SQLITE => 5009,
+        }
+    }
+
+    /*
+       返回给客户端的错误信息
+       错误报文不加密，详细信息可能包含数据库、主机和路径，只记录在服务端日志中
+    */
+    pub fn public_message(&self) -> &'static str {
+        match self {
+            ErrorKind::DATA_INVALID => "invalid data",
+            ErrorKind::DATA_PACK => "pack failed",
+            ErrorKind::DATA_TYPE => "unsupported data type",
+            ErrorKind::DATA_UNPACK_OLDDATA_NOMATCH => "unpack failed",
+            ErrorKind::DATA_IO => "io error",
+            ErrorKind::OS_FromUtf8Error => "invalid utf-8 data",
+            ErrorKind::SESSION_NOT_FOUND => "session not found",
+            ErrorKind::SESSION_EXPIRED => "session expired",
+            ErrorKind::SESSION_KEY => "session key not negotiated",
+            ErrorKind::KEY_NOT_FOUND => "key not found",
+            ErrorKind::SM2_EVP_PKEY | ErrorKind::ERROR_STACK => "crypto error",
+            ErrorKind::KEY_DECRYPT => "decrypt failed",
+            ErrorKind::CERT_EXPIRED => "certificate expired",
+            ErrorKind::KEY_REVOKED => "client key revoked",
+            ErrorKind::CLIENT_CERT => "client certificate mismatch",
+            ErrorKind::APP_NOT_ALLOWED => "app not allowed",
+            ErrorKind::APP_NOT_FOUND => "app not found",
+            ErrorKind::RATE_LIMITED => "rate limit exceeded",
+            ErrorKind::MYSQL
+            | ErrorKind::MYSQL_NO_DATA
+            | ErrorKind::REDIS
+            | ErrorKind::SERDE_JSON
+            | ErrorKind::TOML_DESERIALIZE
+            | ErrorKind::OS_POISONERROR
+            | ErrorKind::CONFIG
+            | ErrorKind::POSTGRES
+            | ErrorKind::SQLITE => "internal server error",
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -77,15 +193,47 @@ impl From<std::io::Error> for Error {
         Error {
             code: ErrorKind::DATA_IO,
             msg: String::from(err.to_string()),
+            retry_after: None,
         }
     }
 }
 
 impl From<mysql::Error> for Error {
     fn from(err: mysql::Error) -> Self {
+        // 连接类错误建议客户端稍后重试
+        let retry_after = match err {
+            mysql::Error::IoError(_) | mysql::Error::DriverError(_) => Some(1),
+            _ => None,
+        };
         Error {
             code: ErrorKind::MYSQL,
             msg: err.to_string(),
+            retry_after: retry_after,
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for Error {
+    fn from(err: tokio_postgres::Error) -> Self {
+        // 连接断开时建议客户端稍后重试
+        let retry_after = match err.is_closed() {
+            true => Some(1),
+            false => None,
+        };
+        Error {
+            code: ErrorKind::POSTGRES,
+            msg: err.to_string(),
+            retry_after: retry_after,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error {
+            code: ErrorKind::SQLITE,
+            msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -95,6 +243,7 @@ impl From<toml::de::Error> for Error {
         Error {
             code: ErrorKind::TOML_DESERIALIZE,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -104,6 +253,7 @@ impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, Config>>> for Error {
         Error {
             code: ErrorKind::OS_POISONERROR,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -113,15 +263,22 @@ impl From<FromUtf8Error> for Error {
         Error {
             code: ErrorKind::OS_FromUtf8Error,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
 
 impl From<RedisError> for Error {
     fn from(err: RedisError) -> Self {
+        let retry_after =
+            match err.is_timeout() || err.is_connection_dropped() || err.is_connection_refusal() {
+                true => Some(1),
+                false => None,
+            };
         Error {
             code: ErrorKind::REDIS,
             msg: err.to_string(),
+            retry_after: retry_after,
         }
     }
 }
@@ -131,6 +288,7 @@ impl From<serde_json::Error> for Error {
         Error {
             code: ErrorKind::SERDE_JSON,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -140,6 +298,7 @@ impl From<PoisonError<std::sync::MutexGuard<'_, std::option::Option<Pool>>>> for
         Error {
             code: ErrorKind::MYSQL,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -149,6 +308,7 @@ impl From<mysql::UrlError> for Error {
         Error {
             code: ErrorKind::MYSQL,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -158,6 +318,28 @@ impl From<ErrorStack> for Error {
         Error {
             code: ErrorKind::ERROR_STACK,
             msg: err.to_string(),
+            retry_after: None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_payload() {
+        let err = Error::new(ErrorKind::SESSION_EXPIRED, "session expired").with_retry_after(3);
+        let data = err.to_vec();
+        assert_eq!(data[0..2], 2002u16.to_be_bytes());
+        assert_eq!(data[2..6], 3u32.to_be_bytes());
+        assert_eq!(data[6..8], 15u16.to_be_bytes());
+        assert_eq!(&data[8..], "session expired".as_bytes());
+
+        // 详细信息不返回给客户端
+        let err = Error::new(ErrorKind::MYSQL, "Access denied for user 'root'@'10.0.0.1'");
+        let data = err.to_vec();
+        assert_eq!(data[0..2], 5001u16.to_be_bytes());
+        assert_eq!(&data[8..], "internal server error".as_bytes());
+    }
+}
@@ -0,0 +1,144 @@
+/*
+   上游主机负载均衡
+   对应 gateway_api.load_balance：0轮询/1IP-Hash/2随机
+   轮询计数按 (app_id, api_name) 保存，跨连接共享
+*/
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use rand::Rng;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Strategy {
+    RoundRobin,
+    IpHash,
+    Random,
+}
+
+impl Strategy {
+    // 未知取值按轮询处理
+    pub fn from_value(value: i32) -> Strategy {
+        match value {
+            1 => Strategy::IpHash,
+            2 => Strategy::Random,
+            _ => Strategy::RoundRobin,
+        }
+    }
+}
+
+pub struct Balancer {
+    counters: Mutex<HashMap<(usize, String), usize>>,
+}
+
+impl Balancer {
+    pub fn new() -> Balancer {
+        Balancer {
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn select<'a>(
+        &self,
+        app_id: usize,
+        api_name: &str,
+        strategy: Strategy,
+        hosts: &'a [String],
+        client_ip: &IpAddr,
+    ) -> Option<&'a String> {
+        if hosts.is_empty() {
+            return None;
+        }
+        let index = match strategy {
+            Strategy::RoundRobin => {
+                let mut counters = self.counters.lock().unwrap();
+                let counter = counters.entry((app_id, api_name.to_string())).or_insert(0);
+                let index = *counter % hosts.len();
+                *counter = counter.wrapping_add(1);
+                index
+            }
+            Strategy::IpHash => ip_hash(client_ip) as usize % hosts.len(),
+            Strategy::Random => rand::thread_rng().gen_range(0..hosts.len()),
+        };
+        hosts.get(index)
+    }
+}
+
+/*
+   FNV-1a，保证多个节点对同一IP选择相同的主机
+*/
+fn ip_hash(ip: &IpAddr) -> u32 {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let mut hash: u32 = 0x811c9dc5;
+    for octet in octets {
+        hash ^= octet as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hosts() -> Vec<String> {
+        vec![
+            "10.0.0.1:80".to_string(),
+            "10.0.0.2:80".to_string(),
+            "10.0.0.3:80".to_string(),
+        ]
+    }
+
+    #[test]
+    fn round_robin() {
+        let balancer = Balancer::new();
+        let hosts = hosts();
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let selected: Vec<&String> = (0..6)
+            .map(|_| {
+                balancer
+                    .select(1, "user", Strategy::RoundRobin, &hosts, &ip)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            selected,
+            vec![&hosts[0], &hosts[1], &hosts[2], &hosts[0], &hosts[1], &hosts[2]]
+        );
+        // 不同接口的计数互不影响
+        assert_eq!(
+            balancer.select(1, "order", Strategy::RoundRobin, &hosts, &ip),
+            Some(&hosts[0])
+        );
+    }
+
+    #[test]
+    fn ip_hash_stable() {
+        let balancer = Balancer::new();
+        let hosts = hosts();
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let first = balancer.select(1, "user", Strategy::IpHash, &hosts, &ip);
+        for _ in 0..10 {
+            assert_eq!(
+                first,
+                balancer.select(1, "user", Strategy::IpHash, &hosts, &ip)
+            );
+        }
+    }
+
+    #[test]
+    fn random_and_empty() {
+        let balancer = Balancer::new();
+        let hosts = hosts();
+        let ip: IpAddr = "::1".parse().unwrap();
+        for _ in 0..10 {
+            let host = balancer.select(1, "user", Strategy::Random, &hosts, &ip);
+            assert!(hosts.contains(host.unwrap()));
+        }
+        assert_eq!(balancer.select(1, "user", Strategy::Random, &[], &ip), None);
+        assert_eq!(Strategy::from_value(1), Strategy::IpHash);
+        assert_eq!(Strategy::from_value(9), Strategy::RoundRobin);
+    }
+}
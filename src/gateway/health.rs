@@ -0,0 +1,217 @@
+/*
+   上游主机健康检查
+   1 主动检查：定时请求配置的 path，失败计数与被动检查共用
+   2 被动检查：转发失败连续达到阈值后摘除主机
+   摘除的主机在冷却时间后重新加入，主动检查成功也会立即恢复
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::{client::HttpConnector, Client, Uri};
+
+use super::proxy;
+use crate::config;
+
+struct HostState {
+    failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+pub struct HealthChecker {
+    hosts: Mutex<HashMap<String, HostState>>,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl HealthChecker {
+    pub fn new(config: &config::HealthCheck) -> HealthChecker {
+        HealthChecker {
+            hosts: Mutex::new(HashMap::new()),
+            failure_threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_secs(config.cooldown_secs),
+        }
+    }
+
+    // 记录需要主动检查的主机
+    pub fn register(&self, hosts: &[String]) {
+        let mut states = self.hosts.lock().unwrap();
+        for host in hosts {
+            states.entry(host.clone()).or_insert(HostState {
+                failures: 0,
+                ejected_until: None,
+            });
+        }
+    }
+
+    fn is_healthy_at(&self, host: &str, now: Instant) -> bool {
+        let mut states = self.hosts.lock().unwrap();
+        match states.get_mut(host) {
+            Some(state) => match state.ejected_until {
+                Some(until) if now < until => false,
+                Some(_) => {
+                    // 冷却结束，重新加入
+                    state.failures = 0;
+                    state.ejected_until = None;
+                    true
+                }
+                None => true,
+            },
+            None => true,
+        }
+    }
+
+    /*
+       过滤出健康的主机，全部不健康时返回原列表，避免接口完全不可用
+    */
+    pub fn healthy_hosts(&self, hosts: &[String]) -> Vec<String> {
+        let now = Instant::now();
+        let healthy: Vec<String> = hosts
+            .iter()
+            .filter(|host| self.is_healthy_at(host, now))
+            .cloned()
+            .collect();
+        match healthy.is_empty() {
+            true => hosts.to_vec(),
+            false => healthy,
+        }
+    }
+
+    pub fn report_success(&self, host: &str) {
+        let mut states = self.hosts.lock().unwrap();
+        if let Some(state) = states.get_mut(host) {
+            state.failures = 0;
+            state.ejected_until = None;
+        }
+    }
+
+    pub fn report_failure(&self, host: &str) {
+        self.report_failure_at(host, Instant::now());
+    }
+
+    fn report_failure_at(&self, host: &str, now: Instant) {
+        let mut states = self.hosts.lock().unwrap();
+        let state = states.entry(host.to_string()).or_insert(HostState {
+            failures: 0,
+            ejected_until: None,
+        });
+        state.failures += 1;
+        // 冷却结束后没有经过 is_healthy_at 重新加入时，继续失败也要再次摘除
+        let ejected = state.ejected_until.map_or(false, |until| now < until);
+        if state.failures >= self.failure_threshold && !ejected {
+            println!("gateway upstream {} ejected", host);
+            state.ejected_until = Some(now + self.cooldown);
+        }
+    }
+
+    fn host_list(&self) -> Vec<String> {
+        self.hosts.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/*
+   启动主动健康检查任务
+*/
+pub fn spawn_active_check(
+    checker: Arc<HealthChecker>,
+    client: Client<HttpConnector>,
+    config: &config::HealthCheck,
+) {
+    let path = match &config.path {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let timeout = Duration::from_secs(config.timeout_secs);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for host in checker.host_list() {
+                match probe(&client, &host, &path, timeout).await {
+                    true => checker.report_success(&host),
+                    false => checker.report_failure(&host),
+                }
+            }
+        }
+    });
+}
+
+async fn probe(client: &Client<HttpConnector>, host: &str, path: &str, timeout: Duration) -> bool {
+    let uri: Uri = match proxy::build_uri(host, path, None).parse() {
+        Ok(uri) => uri,
+        Err(_) => return false,
+    };
+    match tokio::time::timeout(timeout, client.get(uri)).await {
+        Ok(Ok(response)) => !response.status().is_server_error(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn checker(cooldown_secs: u64) -> HealthChecker {
+        HealthChecker::new(&config::HealthCheck {
+            path: None,
+            interval_secs: 10,
+            timeout_secs: 3,
+            failure_threshold: 2,
+            cooldown_secs: cooldown_secs,
+        })
+    }
+
+    #[test]
+    fn passive_eject_and_readmit() {
+        let checker = checker(30);
+        let hosts = vec!["10.0.0.1:80".to_string(), "10.0.0.2:80".to_string()];
+        checker.register(&hosts);
+        let now = Instant::now();
+
+        checker.report_failure_at(&hosts[0], now);
+        assert!(checker.is_healthy_at(&hosts[0], now));
+        checker.report_failure_at(&hosts[0], now);
+        assert!(!checker.is_healthy_at(&hosts[0], now));
+        assert_eq!(checker.healthy_hosts(&hosts), vec![hosts[1].clone()]);
+
+        // 冷却时间后重新加入
+        assert!(checker.is_healthy_at(&hosts[0], now + Duration::from_secs(31)));
+        assert_eq!(checker.healthy_hosts(&hosts), hosts);
+    }
+
+    #[test]
+    fn reeject_after_cooldown() {
+        let checker = checker(30);
+        let hosts = vec!["10.0.0.1:80".to_string(), "10.0.0.2:80".to_string()];
+        let now = Instant::now();
+        checker.report_failure_at(&hosts[0], now);
+        checker.report_failure_at(&hosts[0], now);
+        assert!(!checker.is_healthy_at(&hosts[0], now));
+
+        // 冷却结束后没有请求经过 healthy_hosts，主动检查继续失败
+        let later = now + Duration::from_secs(31);
+        checker.report_failure_at(&hosts[0], later);
+        checker.report_failure_at(&hosts[0], later);
+        assert!(!checker.is_healthy_at(&hosts[0], later));
+        assert!(!checker.is_healthy_at(&hosts[0], later + Duration::from_secs(29)));
+        assert!(checker.is_healthy_at(&hosts[0], later + Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn success_resets_and_all_down() {
+        let checker = checker(30);
+        let hosts = vec!["10.0.0.1:80".to_string()];
+        checker.report_failure(&hosts[0]);
+        checker.report_success(&hosts[0]);
+        checker.report_failure(&hosts[0]);
+        assert_eq!(checker.healthy_hosts(&hosts), hosts);
+
+        checker.report_failure(&hosts[0]);
+        // 全部摘除时返回原列表
+        assert_eq!(checker.healthy_hosts(&hosts), hosts);
+    }
+}
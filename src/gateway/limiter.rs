@@ -0,0 +1,185 @@
+/*
+   接口限流
+   对应 gateway_api.threshold_sec，每秒允许的请求数，0表示不限制
+   令牌桶容量等于每秒请求数，按时间连续补充令牌，空闲 1 秒后令牌桶已满，与新建的令牌桶相同
+   1 local 单节点内存限流
+   2 redis 多节点共享限流
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use redis::Script;
+
+use crate::{error, store::redis_pool::RedisPool};
+
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    // 返回 false 表示超出限制
+    async fn acquire(&self, key: &str, rate: u32) -> error::Result<bool>;
+}
+
+/*
+   限流 key，按 (app_id, api_name) 区分，per_client 时再按客户端唯一标识区分
+*/
+pub fn limit_key(app_id: usize, api_name: &str, serialid: Option<&str>) -> String {
+    match serialid {
+        Some(serialid) => format!("{}:{}:{}", app_id, api_name, serialid),
+        None => format!("{}:{}", app_id, api_name),
+    }
+}
+
+// 清理空闲令牌桶的间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+// 空闲超过该时间的令牌桶已满，可以删除
+const BUCKET_IDLE: Duration = Duration::from_secs(1);
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+struct Buckets {
+    entries: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+pub struct LocalLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+impl LocalLimiter {
+    pub fn new() -> LocalLimiter {
+        LocalLimiter {
+            buckets: Mutex::new(Buckets {
+                entries: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    fn acquire_at(&self, key: &str, rate: u32, now: Instant) -> bool {
+        let rate = rate as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.last_sweep) >= SWEEP_INTERVAL {
+            buckets
+                .entries
+                .retain(|_, bucket| now.saturating_duration_since(bucket.last) < BUCKET_IDLE);
+            buckets.last_sweep = now;
+        }
+        let bucket = buckets.entries.entry(key.to_string()).or_insert(Bucket {
+            tokens: rate,
+            last: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for LocalLimiter {
+    async fn acquire(&self, key: &str, rate: u32) -> error::Result<bool> {
+        Ok(self.acquire_at(key, rate, Instant::now()))
+    }
+}
+
+// 使用 redis 服务端时间，避免节点间时钟不一致
+const TOKEN_BUCKET_SCRIPT: &str = r"
+redis.replicate_commands()
+local rate = tonumber(ARGV[1])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1])
+local ts = tonumber(bucket[2])
+if tokens == nil or ts == nil then
+    tokens = rate
+    ts = now
+end
+tokens = math.min(rate, tokens + math.max(0, now - ts) * rate / 1000)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('PEXPIRE', KEYS[1], 2000)
+return allowed
+";
+
+pub struct RedisLimiter {
+    pool: Arc<RedisPool>,
+    script: Script,
+}
+
+impl RedisLimiter {
+    pub fn new(pool: Arc<RedisPool>) -> RedisLimiter {
+        RedisLimiter {
+            pool: pool,
+            script: Script::new(TOKEN_BUCKET_SCRIPT),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for RedisLimiter {
+    async fn acquire(&self, key: &str, rate: u32) -> error::Result<bool> {
+        let key = format!("stserver:ratelimit:{}", key);
+        let script = &self.script;
+        let allowed: i32 = self
+            .pool
+            .run(|mut conn| async move { script.key(key).arg(rate).invoke_async(&mut conn).await })
+            .await?;
+        Ok(allowed == 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn local_token_bucket() {
+        let limiter = LocalLimiter::new();
+        let now = Instant::now();
+        assert!(limiter.acquire_at("1:user", 2, now));
+        assert!(limiter.acquire_at("1:user", 2, now));
+        assert!(!limiter.acquire_at("1:user", 2, now));
+        // 不同 key 互不影响
+        assert!(limiter.acquire_at("1:order", 2, now));
+        // 半秒补充一个令牌
+        assert!(limiter.acquire_at("1:user", 2, now + Duration::from_millis(500)));
+        assert!(!limiter.acquire_at("1:user", 2, now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn evict_idle_buckets() {
+        let limiter = LocalLimiter::new();
+        let now = Instant::now();
+        assert!(limiter.acquire_at("1:user:a", 2, now));
+        assert!(limiter.acquire_at("1:user:b", 2, now + SWEEP_INTERVAL - BUCKET_IDLE));
+        assert!(limiter.acquire_at("1:user:c", 2, now + SWEEP_INTERVAL));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets.entries.contains_key("1:user:a"));
+        assert_eq!(buckets.entries.len(), 1);
+    }
+
+    #[test]
+    fn key() {
+        assert_eq!(limit_key(1, "user", None), "1:user");
+        assert_eq!(limit_key(1, "user", Some("abc")), "1:user:abc");
+    }
+}
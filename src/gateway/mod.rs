@@ -0,0 +1,269 @@
+/*
+   HTTP 反向代理网关
+   业务请求中携带 api_name，根据 token 关联的 app 在 gateway_api 表中查找上游配置，
+   转发到上游主机后将上游响应作为业务数据加密返回
+   请求和响应的 body 为 base64 编码的原始字节，支持二进制内容
+*/
+
+pub mod balancer;
+mod health;
+mod limiter;
+mod proxy;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use hyper::{client::HttpConnector, Client};
+use serde::{Deserialize, Serialize};
+
+use self::{
+    balancer::{Balancer, Strategy},
+    health::HealthChecker,
+    limiter::{LocalLimiter, RateLimiter, RedisLimiter},
+};
+use crate::{
+    channel::business::{BusinessHandler, Context},
+    config,
+    error::{self, Error, ErrorKind},
+    store::{db::GatewayApi, redis_pool},
+};
+
+// 解密后的业务请求
+#[derive(Deserialize)]
+pub struct GatewayRequest {
+    pub api_name: String,
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default, with = "base64_body")]
+    pub body: Vec<u8>,
+}
+
+// 返回给客户端的业务响应
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GatewayResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default, with = "base64_body")]
+    pub body: Vec<u8>,
+}
+
+impl GatewayResponse {
+    pub fn status(status: u16, body: &str) -> GatewayResponse {
+        GatewayResponse {
+            status: status,
+            headers: HashMap::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+}
+
+mod base64_body {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        base64::decode(value).map_err(D::Error::custom)
+    }
+}
+
+pub struct GatewayHandler {
+    client: Client<HttpConnector>,
+    balancer: Balancer,
+    health: Arc<HealthChecker>,
+    limiter: Box<dyn RateLimiter>,
+    per_client_limit: bool,
+    limit_fail_open: bool,
+    timeout: Duration,
+}
+
+impl GatewayHandler {
+    pub fn new(config: &config::Gateway) -> error::Result<GatewayHandler> {
+        let limiter: Box<dyn RateLimiter> = match config.rate_limit.backend.as_str() {
+            "local" => Box::new(LocalLimiter::new()),
+            "redis" => Box::new(RedisLimiter::new(redis_pool::pool()?)),
+            _ => return Err(Error::new(ErrorKind::CONFIG, "unknown rate_limit backend")),
+        };
+        let client = Client::new();
+        let health = Arc::new(HealthChecker::new(&config.health_check));
+        health::spawn_active_check(health.clone(), client.clone(), &config.health_check);
+        Ok(GatewayHandler {
+            client: client,
+            balancer: Balancer::new(),
+            health: health,
+            limiter: limiter,
+            per_client_limit: config.rate_limit.per_client,
+            limit_fail_open: config.rate_limit.fail_open,
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    /*
+       threshold_sec 小于等于0不限制
+       限流服务异常时按 fail_open 配置放行或者拒绝，放行时限流失效，需关注错误日志
+    */
+    async fn allow(&self, ctx: &Context, api: &GatewayApi) -> bool {
+        let rate = api.threshold_sec.unwrap_or(0);
+        if rate <= 0 {
+            return true;
+        }
+        let serialid = match self.per_client_limit {
+            true => Some(ctx.session.serialid.as_str()),
+            false => None,
+        };
+        let key = limiter::limit_key(api.app_id, &api.api_name, serialid);
+        match self.limiter.acquire(&key, rate as u32).await {
+            Ok(allowed) => allowed,
+            Err(err) => {
+                eprintln!(
+                    "rate limiter error, {} request: {:?}",
+                    if self.limit_fail_open {
+                        "allow"
+                    } else {
+                        "reject"
+                    },
+                    err
+                );
+                self.limit_fail_open
+            }
+        }
+    }
+
+    async fn forward(
+        &self,
+        ctx: &Context,
+        request: &GatewayRequest,
+    ) -> error::Result<GatewayResponse> {
+        let api = match GatewayApi::get(ctx.session.app_id, &request.api_name).await? {
+            Some(api) => api,
+            None => return Ok(GatewayResponse::status(404, "not found gateway_api record")),
+        };
+        if !api.enabled() {
+            return Ok(GatewayResponse::status(403, "gateway api disabled"));
+        }
+        if !self.allow(ctx, &api).await {
+            // 令牌桶 1 秒内至少补充一个令牌
+            return Err(
+                Error::new(ErrorKind::RATE_LIMITED, "rate limit exceeded").with_retry_after(1)
+            );
+        }
+        let hosts = api.host_list();
+        self.health.register(&hosts);
+        let hosts = self.health.healthy_hosts(&hosts);
+        let strategy = Strategy::from_value(api.load_balance.unwrap_or(0));
+        let host = match self.balancer.select(
+            api.app_id,
+            &api.api_name,
+            strategy,
+            &hosts,
+            &ctx.peer_addr.ip(),
+        ) {
+            Some(host) => host,
+            None => return Ok(GatewayResponse::status(502, "no upstream host")),
+        };
+
+        let response = proxy::forward(&self.client, host, &api, request, ctx, self.timeout).await;
+        match &response {
+            Ok(response) if response.status < 500 => self.health.report_success(host),
+            _ => self.health.report_failure(host),
+        }
+        match response {
+            Ok(response) => Ok(response),
+            Err(proxy::ProxyError::Timeout) => Ok(GatewayResponse::status(504, "upstream timeout")),
+            Err(proxy::ProxyError::Upstream(msg)) => Ok(GatewayResponse::status(502, &msg)),
+        }
+    }
+}
+
+#[async_trait]
+impl BusinessHandler for GatewayHandler {
+    async fn handle(&self, ctx: &Context, data: Vec<u8>) -> error::Result<Vec<u8>> {
+        let request: GatewayRequest = serde_json::from_slice(&data)?;
+        let response = self.forward(ctx, &request).await?;
+        Ok(serde_json::to_vec(&response)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::cache::Session;
+
+    struct FailingLimiter;
+
+    #[async_trait]
+    impl RateLimiter for FailingLimiter {
+        async fn acquire(&self, _key: &str, _rate: u32) -> error::Result<bool> {
+            Err(Error::new(ErrorKind::REDIS, "redis unavailable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn limiter_failure() {
+        let health_config = config::HealthCheck::default();
+        let mut handler = GatewayHandler {
+            client: Client::new(),
+            balancer: Balancer::new(),
+            health: Arc::new(HealthChecker::new(&health_config)),
+            limiter: Box::new(FailingLimiter),
+            per_client_limit: false,
+            limit_fail_open: true,
+            timeout: Duration::from_secs(1),
+        };
+        let ctx = Context {
+            peer_addr: "127.0.0.1:3443".parse().unwrap(),
+            client_subject: None,
+            hostname: None,
+            session: Session::init(
+                &vec![0; 40],
+                1,
+                "abc",
+                &vec![],
+                &vec![],
+                &vec![],
+                &vec![],
+                &vec![],
+            ),
+        };
+        let mut api = GatewayApi {
+            app_id: 1,
+            api_name: "user".to_string(),
+            hosts: "127.0.0.1:8080".to_string(),
+            req_path: "/user".to_string(),
+            req_method: None,
+            threshold_sec: Some(10),
+            data_req_example: None,
+            data_resp_example: None,
+            use_state: Some(1),
+            load_balance: None,
+        };
+        assert!(handler.allow(&ctx, &api).await);
+        handler.limit_fail_open = false;
+        assert!(!handler.allow(&ctx, &api).await);
+        // 未配置限流的接口不访问限流服务
+        api.threshold_sec = Some(0);
+        assert!(handler.allow(&ctx, &api).await);
+    }
+
+    #[test]
+    fn binary_body() {
+        let request: GatewayRequest =
+            serde_json::from_str(r#"{"api_name":"upload","body":"AP8Q"}"#).unwrap();
+        assert_eq!(request.body, vec![0x00, 0xff, 0x10]);
+
+        let response = GatewayResponse {
+            status: 200,
+            headers: HashMap::new(),
+            body: vec![0x00, 0xff, 0x10],
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(r#""body":"AP8Q""#));
+        assert!(serde_json::from_str::<GatewayRequest>(r#"{"api_name":"a","body":"%%"}"#).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+/*
+   将业务请求转发到上游主机
+*/
+
+use std::{collections::HashMap, time::Duration};
+
+use hyper::{client::HttpConnector, Body, Client, HeaderMap, Method, Request};
+
+use super::{GatewayRequest, GatewayResponse};
+use crate::{channel::business::Context, store::db::GatewayApi};
+
+pub enum ProxyError {
+    Timeout,
+    Upstream(String),
+}
+
+impl From<hyper::Error> for ProxyError {
+    fn from(err: hyper::Error) -> Self {
+        ProxyError::Upstream(err.to_string())
+    }
+}
+
+impl From<hyper::http::Error> for ProxyError {
+    fn from(err: hyper::http::Error) -> Self {
+        ProxyError::Upstream(err.to_string())
+    }
+}
+
+// 逐跳头部只对单个连接有效，不转发
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/*
+   是否转发头部，除逐跳头部外还去掉 Connection 中列出的头部
+   host 和 content-length 由 hyper 根据上游地址和 body 重新生成
+*/
+fn forwardable(name: &str, connection: &[String]) -> bool {
+    let name = name.to_ascii_lowercase();
+    !HOP_BY_HOP_HEADERS.contains(&name.as_str())
+        && name != "host"
+        && name != "content-length"
+        && !connection.contains(&name)
+}
+
+// Connection 头部列出的头部名称
+fn connection_headers<'a, I: Iterator<Item = (&'a str, &'a str)>>(headers: I) -> Vec<String> {
+    headers
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn response_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    let headers: Vec<(&str, &str)> = headers
+        .iter()
+        .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str(), value)))
+        .collect();
+    let connection = connection_headers(headers.iter().cloned());
+    headers
+        .into_iter()
+        .filter(|(name, _)| {
+            forwardable(name, &connection) || name.eq_ignore_ascii_case("content-length")
+        })
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/*
+   host 可以是 ip:port 或者带 http:// 前缀的地址
+*/
+pub fn build_uri(host: &str, path: &str, query: Option<&str>) -> String {
+    let host = host.trim_end_matches('/');
+    let mut uri = match host.starts_with("http://") {
+        true => host.to_string(),
+        false => format!("http://{}", host),
+    };
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    uri.push_str(path);
+    if let Some(query) = query {
+        if !query.is_empty() {
+            uri.push('?');
+            uri.push_str(query.trim_start_matches('?'));
+        }
+    }
+    uri
+}
+
+pub async fn forward(
+    client: &Client<HttpConnector>,
+    host: &str,
+    api: &GatewayApi,
+    request: &GatewayRequest,
+    ctx: &Context,
+    timeout: Duration,
+) -> Result<GatewayResponse, ProxyError> {
+    let method = api.req_method.as_deref().unwrap_or("POST");
+    let method = Method::from_bytes(method.to_uppercase().as_bytes())
+        .map_err(|err| ProxyError::Upstream(err.to_string()))?;
+    let uri = build_uri(host, &api.req_path, request.query.as_deref());
+
+    let mut builder = Request::builder().method(method).uri(uri);
+    let connection = connection_headers(
+        request
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    for (name, value) in request.headers.iter() {
+        if forwardable(name, &connection) {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    builder = builder.header("X-Forwarded-For", ctx.peer_addr.ip().to_string());
+    let upstream_request = builder.body(Body::from(request.body.clone()))?;
+
+    let fut = async {
+        let response = client.request(upstream_request).await?;
+        let status = response.status().as_u16();
+        let headers = response_headers(response.headers());
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok(GatewayResponse {
+            status: status,
+            headers: headers,
+            body: body.to_vec(),
+        })
+    };
+
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(response) => response,
+        Err(_) => Err(ProxyError::Timeout),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uri() {
+        assert_eq!(
+            build_uri("127.0.0.1:8080", "/api/user", None),
+            "http://127.0.0.1:8080/api/user"
+        );
+        assert_eq!(
+            build_uri("http://backend/", "api/user", Some("?id=1")),
+            "http://backend/api/user?id=1"
+        );
+    }
+
+    #[test]
+    fn strip_hop_by_hop() {
+        let headers = vec![
+            ("Connection", "keep-alive, X-Trace"),
+            ("X-Trace", "1"),
+            ("Host", "client.example"),
+            ("Transfer-Encoding", "chunked"),
+            ("Content-Type", "application/json"),
+        ];
+        let connection = connection_headers(headers.iter().cloned());
+        let forwarded: Vec<&str> = headers
+            .iter()
+            .filter(|(name, _)| forwardable(name, &connection))
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(forwarded, vec!["Content-Type"]);
+    }
+}
@@ -1,6 +1,8 @@
 mod channel;
+mod command;
 mod config;
 mod error;
+mod gateway;
 mod server;
 mod sm;
 mod store;
@@ -10,9 +12,11 @@ use std::{
     fs::File,
     io::{self, Read},
     path::Path,
+    sync::Arc,
 };
 
 use crate::server::Server;
+use crate::store::mem;
 use clap::{App, Arg};
 use daemonize::Daemonize;
 
@@ -29,6 +33,9 @@ async fn main() -> error::Result<()> {
                 .short("c"),
         )
         .arg(Arg::from_usage("-d, --daemon 'Set process backgroud run'").short("d"))
+        .subcommand(command::migrate::subcommand())
+        .subcommand(command::keys::subcommand())
+        .subcommand(command::ca::subcommand())
         .get_matches();
 
     if let Some(c) = matches.value_of("config") {
@@ -37,6 +44,18 @@ async fn main() -> error::Result<()> {
         config::parse_config(c).unwrap();
     }
 
+    store::envelope::init(&*mem::CONFIG.lock()?)?;
+
+    if let Some(migrate) = matches.subcommand_matches("migrate") {
+        return command::migrate::run(migrate).await;
+    }
+    if let Some(keys) = matches.subcommand_matches("keys") {
+        return command::keys::run(keys).await;
+    }
+    if let Some(ca) = matches.subcommand_matches("ca") {
+        return command::ca::run(ca).await;
+    }
+
     if let Some(daemon_idx) = matches.index_of("daemon") {
         if daemon_idx > 0 {
             println!("daemon is open");
@@ -66,9 +85,34 @@ async fn main() -> error::Result<()> {
         }
     }
 
+    {
+        // 复制一份配置，初始化过程中的 await 不持有锁
+        let config = mem::CONFIG.lock()?.clone();
+        if let Some(mysql) = &config.mysql {
+            store::mysql_pool::init(mysql).await?;
+        }
+        if let Some(redis) = &config.redis {
+            store::redis_pool::init(redis).await?;
+        }
+        store::session::init(&config)?;
+        store::repository::init(&config).await?;
+        store::keycache::init(&config);
+        store::denylist::init().await?;
+        store::denylist::spawn_sync(&config);
+        store::certpool::spawn_monitor(&config);
+        if let Some(gateway) = &config.gateway {
+            let handler = gateway::GatewayHandler::new(gateway)?;
+            channel::business::register_handler(Arc::new(handler));
+        }
+    }
+
     println!("stserver start......");
 
-    let server = Server::new();
+    let server = Arc::new(Server::new()?);
+    server::spawn_reload(
+        server.clone(),
+        matches.value_of("config").unwrap().to_string(),
+    )?;
     server::run(&server).await?;
     Ok(())
 }
@@ -5,6 +5,13 @@ use std::ptr;
 
 use libc::*;
 
+use foreign_types::ForeignTypeRef;
+use openssl::{
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{PKey, PKeyRef, Private},
+};
+
 use crate::error::{self, Error, ErrorKind};
 
 pub const EVP_PKEY_SM2: c_int = NID_sm2;
@@ -163,6 +170,39 @@ impl SM4 {
 pub struct SM2 {}
 
 impl SM2 {
+    /*
+       生成 SM2 密钥对，返回 (私钥 EC PRIVATE KEY PEM, 公钥 PUBLIC KEY PEM)
+    */
+    pub fn generate_keypair() -> error::Result<(Vec<u8>, Vec<u8>)> {
+        let group = EcGroup::from_curve_name(Nid::SM2)?;
+        let key = EcKey::generate(&group)?;
+        Ok((key.private_key_to_pem()?, key.public_key_to_pem()?))
+    }
+
+    /*
+       生成用于证书签发的 SM2 PKey
+    */
+    pub fn generate_pkey() -> error::Result<PKey<Private>> {
+        let group = EcGroup::from_curve_name(Nid::SM2)?;
+        let pkey = PKey::from_ec_key(EcKey::generate(&group)?)?;
+        SM2::alias_pkey(&pkey)?;
+        Ok(pkey)
+    }
+
+    /*
+       设置 SM2 别名，否则 X509 签名和验签按 ECDSA 处理
+       从 PKCS#12 中读出的 SM2 私钥签发证书前也需要设置
+    */
+    pub fn alias_pkey<T>(pkey: &PKeyRef<T>) -> error::Result<()> {
+        if unsafe { EVP_PKEY_set_alias_type(pkey.as_ptr(), EVP_PKEY_SM2) } != 1 {
+            return Err(Error::new(
+                ErrorKind::SM2_EVP_PKEY,
+                "EVP_PKEY_set_alias_type failed",
+            ));
+        }
+        Ok(())
+    }
+
     fn create_evp_pkey(key: &Vec<u8>, is_pub: bool) -> error::Result<*mut EVP_PKEY> {
         unsafe {
             let mut evp_key = EVP_PKEY_new();
@@ -275,7 +315,7 @@ impl SM2 {
                 data.len(),
             );
             let mut cipher_text = vec![0; *ciphertext_len].into_boxed_slice();
-            EVP_PKEY_decrypt(
+            let res = EVP_PKEY_decrypt(
                 ectx,
                 cipher_text.as_mut_ptr(),
                 ciphertext_len,
@@ -284,6 +324,11 @@ impl SM2 {
             );
             EVP_PKEY_free(pkey);
             EVP_PKEY_CTX_free(ectx);
+            // 密钥不匹配或者密文被篡改时解密失败
+            if res != 1 {
+                drop(Box::from_raw(ciphertext_len));
+                return Err(Error::new(ErrorKind::KEY_DECRYPT, "SM2 decrypt failed"));
+            }
             // 处理返回值的长度
             let mut result_vec = cipher_text.to_vec();
             result_vec.truncate(*ciphertext_len);
@@ -451,4 +496,17 @@ v6T0BhtziIZx5XKcnj1NnUvbDXLMUBv1v60nxmNYvzACZ1/HMTpmi7jCRg==
         println!("sign_data len: {}", sign_data.len());
         assert_eq!(true, SM2::verify(&sign_data, &buffer, &public_key).unwrap());
     }
+
+    #[test]
+    fn sm2_generate_keypair() {
+        let (private_key, public_key) = SM2::generate_keypair().unwrap();
+        let data = vec![1, 2, 3];
+        let sign_data = SM2::sign(&data, &private_key).unwrap();
+        assert!(SM2::verify(&sign_data, &data, &public_key).unwrap());
+        let enc_data = SM2::encrypt(&data, &public_key).unwrap();
+        assert_eq!(SM2::decrypt(&enc_data, &private_key).unwrap(), data);
+
+        let (other_key, _) = SM2::generate_keypair().unwrap();
+        assert!(SM2::decrypt(&enc_data, &other_key).is_err());
+    }
 }
@@ -1,17 +1,33 @@
-use std::{time::Duration, vec};
+use std::{sync::Arc, time::Duration, vec};
 
-use redis::{Client, Commands, Connection};
+use async_trait::async_trait;
+use chrono::Utc;
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
 use serde::{Deserialize, Serialize};
 
-use super::mem;
+use super::{
+    mem,
+    redis_pool::RedisPool,
+    session::{self, SessionStore},
+};
 use crate::{
-    error::{Error, Result},
+    config,
+    error::{Error, ErrorKind, Result},
     sm::SM2,
 };
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     pub token: Vec<u8>,
+    // 客户端所属项目
+    #[serde(default)]
+    pub app_id: usize,
+    // 客户端唯一标识
+    #[serde(default)]
+    pub serialid: String,
+    // 协商使用的客户端密钥版本
+    #[serde(default)]
+    pub key_version: u32,
     pub random_a: Vec<u8>,
     pub client_mac: Vec<u8>,
     pub random_b: Vec<u8>,
@@ -27,19 +43,30 @@ pub struct Session {
     pub request_hash: Vec<u8>,
     // 协商出的对称密钥
     pub encrypt_key: Vec<u8>,
+    // 创建时间和最后活跃时间，unix 时间戳秒
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub last_active: i64,
 }
 
 impl Session {
     pub fn init(
         token: &Vec<u8>,
+        app_id: usize,
+        serialid: &str,
         random_a: &Vec<u8>,
         random_b: &Vec<u8>,
         mac: &Vec<u8>,
         prikey: &Vec<u8>,
         request_hash: &Vec<u8>,
     ) -> Session {
+        let now = Utc::now().timestamp();
         Session {
             token: token.to_vec(),
+            app_id: app_id,
+            serialid: serialid.to_string(),
+            key_version: 0,
             random_a: random_a.to_vec(),
             client_mac: mac.to_vec(),
             random_b: random_b.to_vec(),
@@ -50,32 +77,205 @@ impl Session {
             request_hash: request_hash.to_vec(),
             random_cert: vec![],
             encrypt_key: vec![],
+            created_at: now,
+            last_active: now,
+        }
+    }
+
+    pub fn with_key_version(mut self, key_version: u32) -> Session {
+        self.key_version = key_version;
+        self
+    }
+
+    /*
+       会话过期时间，取绝对超时和空闲超时中较早的一个，均为0时永不过期
+    */
+    fn expires_at(&self, timeout: &config::Session) -> Option<i64> {
+        let absolute = match timeout.absolute_timeout_secs {
+            0 => None,
+            secs => Some(self.created_at + secs as i64),
+        };
+        let idle = match timeout.idle_timeout_secs {
+            0 => None,
+            secs => Some(self.last_active + secs as i64),
+        };
+        match (absolute, idle) {
+            (Some(a), Some(i)) => Some(a.min(i)),
+            (a, i) => a.or(i),
         }
     }
 
-    pub fn get(token: Vec<u8>) -> Result<Session> {
-        let mut con = init_connect()?;
+    fn is_expired(&self, now: i64, timeout: &config::Session) -> bool {
+        match self.expires_at(timeout) {
+            Some(expires_at) => now >= expires_at,
+            None => false,
+        }
+    }
 
-        let session: String = con.get(token)?;
-        let str: Session = serde_json::from_str(&session)?;
-        Ok(str)
+    pub async fn get(token: Vec<u8>) -> Result<Session> {
+        let timeout = session_timeout()?;
+        let session = match session::store()?.get(&token).await? {
+            Some(session) => session,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::SESSION_NOT_FOUND,
+                    "not found session",
+                ))
+            }
+        };
+        if session.is_expired(Utc::now().timestamp(), &timeout) {
+            return Err(Error::new(ErrorKind::SESSION_EXPIRED, "session expired"));
+        }
+        Ok(session)
     }
 
-    pub fn set(&self) -> Result<()> {
-        let mut conn = init_connect()?;
-        let session = serde_json::to_string(&self)?;
-        conn.set(self.token.clone(), session)?;
+    /*
+       过期的会话在存储中多保留 expired_retention_secs，
+       期间请求返回会话过期而不是会话不存在
+    */
+    pub async fn set(&self) -> Result<()> {
+        let ttl = self.ttl(&session_timeout()?);
+        session::store()?.set(self, ttl).await
+    }
 
-        Ok(())
+    fn ttl(&self, timeout: &config::Session) -> Option<Duration> {
+        self.expires_at(timeout).map(|expires_at| {
+            let secs = (expires_at - Utc::now().timestamp()).max(0) as u64
+                + timeout.expired_retention_secs;
+            Duration::from_secs(secs.max(1))
+        })
+    }
+
+    /*
+       刷新空闲超时
+       业务报文并发处理，只更新仍然存在的会话，避免与注销并发时重新写入已删除的会话
+    */
+    pub async fn touch(&mut self) -> Result<()> {
+        self.last_active = Utc::now().timestamp();
+        let ttl = self.ttl(&session_timeout()?);
+        match session::store()?.touch(self, ttl).await? {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::SESSION_NOT_FOUND,
+                "not found session",
+            )),
+        }
+    }
+
+    pub async fn delete(token: Vec<u8>) -> Result<()> {
+        session::store()?.delete(&token).await
     }
 }
 
-fn init_connect() -> Result<Connection> {
+fn session_timeout() -> Result<config::Session> {
     let config = &*mem::CONFIG.lock()?;
-    let redis_url = &config.redis.as_ref().unwrap().url;
-    let client = Client::open(redis_url.as_str())?;
-    let mut con = client.get_connection()?;
-    con.set_read_timeout(Some(Duration::new(50, 0)))?;
-    con.set_write_timeout(Some(Duration::new(50, 0)))?;
-    Ok(con)
+    Ok(match &config.session {
+        Some(session) => session.clone(),
+        None => config::Session::default(),
+    })
+}
+
+/*
+   redis 会话存储，使用共享的异步连接池
+*/
+pub struct RedisSessionStore {
+    pool: Arc<RedisPool>,
+}
+
+impl RedisSessionStore {
+    pub fn new(pool: Arc<RedisPool>) -> RedisSessionStore {
+        RedisSessionStore { pool: pool }
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn get(&self, token: &[u8]) -> Result<Option<Session>> {
+        let session: Option<String> = self
+            .pool
+            .run(|mut conn| async move { conn.get(token).await })
+            .await?;
+        match session {
+            Some(session) => Ok(Some(serde_json::from_str(&session)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, session: &Session, ttl: Option<Duration>) -> Result<()> {
+        let value = serde_json::to_string(session)?;
+        let token = session.token.as_slice();
+        self.pool
+            .run(|mut conn| async move {
+                match ttl {
+                    Some(ttl) => conn.set_ex(token, value, ttl.as_secs()).await,
+                    None => conn.set(token, value).await,
+                }
+            })
+            .await
+    }
+
+    /*
+       SET XX 只在会话存在时写入，协商完成后会话只有 last_active 会变化
+    */
+    async fn touch(&self, session: &Session, ttl: Option<Duration>) -> Result<bool> {
+        let value = serde_json::to_string(session)?;
+        let token = session.token.as_slice();
+        let mut options = SetOptions::default().conditional_set(ExistenceCheck::XX);
+        if let Some(ttl) = ttl {
+            options = options.with_expiration(SetExpiry::EX(ttl.as_secs() as usize));
+        }
+        let res: Option<String> = self
+            .pool
+            .run(|mut conn| async move { conn.set_options(token, value, options).await })
+            .await?;
+        Ok(res.is_some())
+    }
+
+    async fn delete(&self, token: &[u8]) -> Result<()> {
+        self.pool
+            .run(|mut conn| async move { conn.del(token).await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let timeout = config::Session {
+            store: "memory".to_string(),
+            absolute_timeout_secs: 100,
+            idle_timeout_secs: 10,
+            expired_retention_secs: 60,
+        };
+        let mut session = Session::init(
+            &vec![0; 40],
+            1,
+            "abc",
+            &vec![],
+            &vec![],
+            &vec![],
+            &vec![],
+            &vec![],
+        );
+        session.created_at = 1000;
+        session.last_active = 1000;
+        assert!(!session.is_expired(1009, &timeout));
+        assert!(session.is_expired(1010, &timeout));
+
+        // 活跃的会话仍受绝对超时限制
+        session.last_active = 1095;
+        assert!(!session.is_expired(1099, &timeout));
+        assert!(session.is_expired(1100, &timeout));
+
+        let unlimited = config::Session {
+            store: "memory".to_string(),
+            absolute_timeout_secs: 0,
+            idle_timeout_secs: 0,
+            expired_retention_secs: 0,
+        };
+        assert_eq!(session.expires_at(&unlimited), None);
+    }
 }
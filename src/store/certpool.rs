@@ -0,0 +1,224 @@
+/*
+   项目证书池
+   1 握手时优先使用当前证书库，当前证书库没有有效证书时回退到轮换前的证书库
+   2 定时检查所有项目的证书库，打印已过期和即将过期的证书
+     每个证书库输出一行 cert_pool 开头的 key=value 日志，包含剩余天数，供日志采集生成指标
+   轮换：导入新证书库(原证书库自动保留为上一版) -> 确认新证书库可用 -> keys retire 删除上一版
+   已建立的会话在协商时保存了证书库私钥，轮换不影响进行中的会话
+*/
+
+use std::time::Duration;
+
+use super::{db::App, envelope, repository};
+use crate::{
+    config,
+    error::{Error, ErrorKind, Result},
+    utils,
+};
+
+/*
+   项目的证书库和解密后的密码，当前证书库在前
+   密码单独解密，一个证书库的密码无法解密不影响另一个
+*/
+pub fn keystores(app: &App) -> Vec<(&'static str, &[u8], Result<String>)> {
+    let mut res = Vec::new();
+    if let (Some(certs), Some(passwd)) = (&app.certs, &app.certs_passwd) {
+        res.push(("current", certs.as_slice(), envelope::open_string(passwd)));
+    }
+    if let (Some(certs), Some(passwd)) = (&app.prev_certs, &app.prev_certs_passwd) {
+        res.push(("previous", certs.as_slice(), envelope::open_string(passwd)));
+    }
+    res
+}
+
+/*
+   握手使用的证书库私钥和随机证书(der)
+*/
+pub fn select(app: &App) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut last_err = Error::new(ErrorKind::KEY_NOT_FOUND, "app keystore not set");
+    for (_, certs, passwd) in keystores(app) {
+        let passwd = match passwd {
+            Ok(passwd) => passwd,
+            Err(err) => {
+                last_err = err;
+                continue;
+            }
+        };
+        match utils::get_random_x509(certs, &passwd) {
+            Ok(cert) => return Ok((utils::prikey_from_pkcs12(certs, &passwd)?, cert)),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/*
+   启动证书有效期检查任务，未配置 [cert_pool] 时使用默认值
+*/
+pub fn spawn_monitor(config: &config::Config) {
+    let default = config::CertPool::default();
+    let cert_pool = config.cert_pool.as_ref().unwrap_or(&default);
+    if cert_pool.check_interval_secs == 0 {
+        return;
+    }
+    let warn_days = cert_pool.warn_days;
+    let interval = Duration::from_secs(cert_pool.check_interval_secs);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = check(warn_days).await {
+                eprintln!("cert pool check error: {:?}", err);
+            }
+        }
+    });
+}
+
+async fn check(warn_days: i32) -> Result<()> {
+    for app in repository::repository()?.apps().await? {
+        for metric in metrics(&app) {
+            println!("{}", metric);
+        }
+        for warning in warnings(&app, warn_days) {
+            println!("{}", warning);
+        }
+    }
+    Ok(())
+}
+
+/*
+   每个证书库一行，valid 为 0 表示证书库不可用
+   cert_pool app=1 keystore=current valid=1 certificates=10 expired=0 min_days_left=12 max_days_left=300
+*/
+pub fn metrics(app: &App) -> Vec<String> {
+    keystores(app)
+        .into_iter()
+        .map(|(label, certs, passwd)| {
+            let expiries = passwd.and_then(|passwd| utils::cert_expiries(certs, &passwd));
+            let expiries = match expiries {
+                Ok(expiries) if !expiries.is_empty() => expiries,
+                _ => return format!("cert_pool app={} keystore={} valid=0", app.id, label),
+            };
+            let days_left = expiries.iter().map(|expiry| expiry.days_left);
+            let min_days_left = days_left.clone().min().unwrap_or_default();
+            let max_days_left = days_left.clone().max().unwrap_or_default();
+            format!(
+                "cert_pool app={} keystore={} valid={} certificates={} expired={} \
+                 min_days_left={} max_days_left={}",
+                app.id,
+                label,
+                (max_days_left >= 0) as u8,
+                expiries.len(),
+                days_left.filter(|days_left| *days_left < 0).count(),
+                min_days_left,
+                max_days_left
+            )
+        })
+        .collect()
+}
+
+pub fn warnings(app: &App, warn_days: i32) -> Vec<String> {
+    let keystores = keystores(app);
+    let mut res = Vec::new();
+    let mut usable = keystores.is_empty();
+    for (label, certs, passwd) in keystores {
+        let passwd = match passwd {
+            Ok(passwd) => passwd,
+            Err(err) => {
+                res.push(format!(
+                    "app {} {} keystore password: {:?}",
+                    app.id, label, err
+                ));
+                continue;
+            }
+        };
+        let expiries = match utils::cert_expiries(certs, &passwd) {
+            Ok(expiries) => expiries,
+            Err(err) => {
+                res.push(format!(
+                    "app {} {} keystore invalid: {:?}",
+                    app.id, label, err
+                ));
+                continue;
+            }
+        };
+        let expired = expiries
+            .iter()
+            .filter(|expiry| expiry.days_left < 0)
+            .count();
+        usable |= expired < expiries.len();
+        if expired > 0 {
+            res.push(format!(
+                "app {} {} keystore: {} of {} certificates expired",
+                app.id,
+                label,
+                expired,
+                expiries.len()
+            ));
+        }
+        for expiry in expiries
+            .iter()
+            .filter(|expiry| expiry.days_left >= 0 && expiry.days_left < warn_days)
+        {
+            res.push(format!(
+                "app {} {} keystore: certificate {} expires in {} days ({})",
+                app.id, label, expiry.subject, expiry.days_left, expiry.not_after
+            ));
+        }
+    }
+    if !usable {
+        res.push(format!(
+            "app {} has no valid certificate, handshakes will fail",
+            app.id
+        ));
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::ca::Authority;
+
+    #[test]
+    fn select_and_warn() {
+        let authority = Authority::create("test CA", 30).unwrap();
+        let keystore = authority.issue_pool("test", 2, 10, "123456").unwrap();
+        let mut app = App {
+            id: 1,
+            name: "demo".to_string(),
+            description: None,
+            certs: Some(vec![1, 2, 3]),
+            certs_passwd: Some("123456".to_string()),
+            prev_certs: Some(keystore),
+            prev_certs_passwd: Some("123456".to_string()),
+        };
+
+        // 当前证书库不可用时回退到上一版
+        assert!(select(&app).is_ok());
+        let res = metrics(&app);
+        assert_eq!(res[0], "cert_pool app=1 keystore=current valid=0");
+        assert!(res[1].starts_with("cert_pool app=1 keystore=previous valid=1 certificates=2"));
+        let res = warnings(&app, 30);
+        assert!(res[0].contains("current keystore invalid"));
+        assert_eq!(
+            res.iter()
+                .filter(|warning| warning.contains("expires in"))
+                .count(),
+            2
+        );
+        assert!(warnings(&app, 5).len() == 1);
+
+        // 当前证书库密码无法解密时仍可使用上一版
+        app.certs_passwd = Some("ENC1:00".to_string());
+        assert!(select(&app).is_ok());
+        assert!(warnings(&app, 30)[0].contains("current keystore password"));
+
+        app.prev_certs = None;
+        assert!(select(&app).is_err());
+        assert!(warnings(&app, 30)
+            .last()
+            .unwrap()
+            .contains("no valid certificate"));
+    }
+}
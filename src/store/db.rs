@@ -1,89 +1,425 @@
-use mysql::{params, prelude::Queryable, Opts, Pool, PooledConn};
+use mysql::{params, prelude::FromValue, prelude::Queryable, Row};
+use serde::{Deserialize, Serialize};
 
-use crate::error::{self};
+use crate::error::{self, Error, ErrorKind};
 
-use super::mem;
+use super::mysql_pool;
 
+/*
+   按列名取值，表结构调整列顺序时不受影响
+*/
+fn column<T: FromValue>(row: &mut Row, name: &str) -> error::Result<T> {
+    match row.take_opt(name) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(err)) => Err(Error::new(
+            ErrorKind::MYSQL,
+            &format!("column {}: {}", name, err),
+        )),
+        None => Err(Error::new(
+            ErrorKind::MYSQL,
+            &format!("missing column {}", name),
+        )),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AppClientKey {
     pub app_id: usize,
     pub client_type: usize,
     pub serialid: Option<String>,
     pub pubkey: Option<String>,
+    // 主密钥加密，使用时解密
     pub prikey: Option<String>,
+    // 密钥版本，轮换时递增
+    pub key_version: u32,
+    // 轮换后旧版本的过期时间，unix 时间戳秒，None 表示不过期
+    pub expires_at: Option<i64>,
+    // 0 正常 1 已吊销
+    pub status: u32,
+    pub revoked_reason: Option<String>,
+    pub revoked_at: Option<i64>,
 }
 
-fn db_global_init() -> error::Result<PooledConn> {
-    let config = &*mem::CONFIG.lock().unwrap();
-    let mysql_name = &config.mysql.as_ref().unwrap().user;
-    let mysql_passwd = &config.mysql.as_ref().unwrap().passwd;
-    let mysql_host = &config.mysql.as_ref().unwrap().host;
-    let mysql_port = &config.mysql.as_ref().unwrap().port;
-    let url = format!(
-        "mysql://{}:{}@{}:{}/stserver",
-        mysql_name, mysql_passwd, mysql_host, mysql_port
-    );
-
-    let mut pool = mem::MYSQL_POOL.lock()?;
-    if pool.is_none() {
-        println!("here is call 444");
-        *pool = Some(Pool::new(Opts::from_url(url.as_str())?)?);
-    }
-    Ok(pool.as_ref().unwrap().get_conn()?)
-}
+pub const KEY_STATUS_REVOKED: u32 = 1;
 
 impl AppClientKey {
-    pub fn get_with_app_client(serialid: &str) -> error::Result<Option<AppClientKey>> {
-        let mut conn = db_global_init()?;
-        let res = conn
-            .exec_first(
-                "select * from stserver.app_client_key where serialid=:serialid",
-                params! {
-                    "serialid" => serialid,
-                },
-            )
-            .map(|row| {
-                row.map(
-                    |(app_id, client_type, serialid, pubkey, prikey)| AppClientKey {
-                        app_id: app_id,
-                        client_type: client_type,
-                        serialid: serialid,
-                        pubkey: pubkey,
-                        prikey: prikey,
+    fn from_row(mut row: Row) -> error::Result<AppClientKey> {
+        Ok(AppClientKey {
+            app_id: column(&mut row, "app_id")?,
+            client_type: column(&mut row, "client_type")?,
+            serialid: column(&mut row, "serialid")?,
+            pubkey: column(&mut row, "pubkey")?,
+            prikey: column(&mut row, "prikey")?,
+            key_version: column(&mut row, "key_version")?,
+            expires_at: column(&mut row, "expires_at")?,
+            status: column(&mut row, "status")?,
+            revoked_reason: column(&mut row, "revoked_reason")?,
+            revoked_at: column(&mut row, "revoked_at")?,
+        })
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.status == KEY_STATUS_REVOKED
+    }
+
+    // 是否在可用期内，轮换窗口内的旧版本仍然可用
+    pub fn accepted_at(&self, now: i64) -> bool {
+        self.expires_at.map_or(true, |expires_at| expires_at > now)
+    }
+
+    // 全部密钥版本，新版本在前
+    pub async fn versions(serialid: &str) -> error::Result<Vec<AppClientKey>> {
+        let serialid = serialid.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                let rows: Vec<Row> = conn.exec(
+                    "select app_id, client_type, serialid, pubkey, prikey, key_version, expires_at, \
+                     status, revoked_reason, revoked_at from app_client_key \
+                     where serialid=:serialid order by key_version desc",
+                    params! {
+                        "serialid" => serialid,
                     },
-                )
-            });
+                )?;
+                rows.into_iter().map(AppClientKey::from_row).collect()
+            })
+            .await
+    }
 
-        Ok(res?)
+    pub async fn list(app_id: Option<usize>) -> error::Result<Vec<AppClientKey>> {
+        mysql_pool::pool()?
+            .run(move |conn| {
+                let rows: Vec<Row> = match app_id {
+                    Some(app_id) => conn.exec(
+                        "select app_id, client_type, serialid, pubkey, prikey, key_version, \
+                         expires_at, status, revoked_reason, revoked_at from app_client_key \
+                         where app_id=:app_id order by serialid, key_version",
+                        params! {
+                            "app_id" => app_id,
+                        },
+                    )?,
+                    None => conn.query(
+                        "select app_id, client_type, serialid, pubkey, prikey, key_version, \
+                         expires_at, status, revoked_reason, revoked_at from app_client_key \
+                         order by app_id, serialid, key_version",
+                    )?,
+                };
+                rows.into_iter().map(AppClientKey::from_row).collect()
+            })
+            .await
+    }
+
+    pub async fn insert(&self) -> error::Result<()> {
+        let key = self.clone();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec_drop(
+                    "insert into app_client_key(app_id, client_type, serialid, pubkey, prikey, \
+                     key_version, expires_at, status, revoked_reason, revoked_at) values \
+                     (:app_id, :client_type, :serialid, :pubkey, :prikey, :key_version, \
+                     :expires_at, :status, :revoked_reason, :revoked_at)",
+                    params! {
+                        "app_id" => key.app_id,
+                        "client_type" => key.client_type,
+                        "serialid" => key.serialid,
+                        "pubkey" => key.pubkey,
+                        "prikey" => key.prikey,
+                        "key_version" => key.key_version,
+                        "expires_at" => key.expires_at,
+                        "status" => key.status,
+                        "revoked_reason" => key.revoked_reason,
+                        "revoked_at" => key.revoked_at,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    // 原地更新指定版本的私钥，用于加密历史数据
+    pub async fn update_prikey(
+        serialid: &str,
+        key_version: u32,
+        prikey: &str,
+    ) -> error::Result<()> {
+        let serialid = serialid.to_string();
+        let prikey = prikey.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec_drop(
+                    "update app_client_key set prikey=:prikey \
+                     where serialid=:serialid and key_version=:key_version",
+                    params! {
+                        "serialid" => serialid,
+                        "key_version" => key_version,
+                        "prikey" => prikey,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    // 设置已有版本的过期时间，已经更早过期的版本不变
+    pub async fn expire(serialid: &str, expires_at: i64) -> error::Result<()> {
+        let serialid = serialid.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec_drop(
+                    "update app_client_key set expires_at=:expires_at where serialid=:serialid \
+                     and (expires_at is null or expires_at>:expires_at)",
+                    params! {
+                        "serialid" => serialid,
+                        "expires_at" => expires_at,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    // 吊销全部版本，返回是否存在
+    pub async fn revoke(serialid: &str, reason: &str, revoked_at: i64) -> error::Result<bool> {
+        let serialid = serialid.to_string();
+        let reason = reason.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                conn.exec_drop(
+                    "update app_client_key set status=:status, revoked_reason=:reason, \
+                     revoked_at=:revoked_at where serialid=:serialid",
+                    params! {
+                        "serialid" => serialid,
+                        "status" => KEY_STATUS_REVOKED,
+                        "reason" => reason,
+                        "revoked_at" => revoked_at,
+                    },
+                )?;
+                Ok(conn.affected_rows() > 0)
+            })
+            .await
+    }
+
+    pub async fn revoked_serialids() -> error::Result<Vec<String>> {
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec(
+                    "select distinct serialid from app_client_key \
+                     where status=:status and serialid is not null",
+                    params! {
+                        "status" => KEY_STATUS_REVOKED,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    pub async fn delete(serialid: &str) -> error::Result<bool> {
+        let serialid = serialid.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                conn.exec_drop(
+                    "delete from app_client_key where serialid=:serialid",
+                    params! {
+                        "serialid" => serialid,
+                    },
+                )?;
+                Ok(conn.affected_rows() > 0)
+            })
+            .await
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct App {
     pub id: usize,
     pub name: String,
     pub description: Option<String>,
     pub certs: Option<Vec<u8>>,
+    // keystore 密码，主密钥加密
+    pub certs_passwd: Option<String>,
+    // 轮换前的证书库，当前证书库没有有效证书时使用
+    pub prev_certs: Option<Vec<u8>>,
+    pub prev_certs_passwd: Option<String>,
 }
 
 impl App {
-    pub fn get(id: usize) -> error::Result<Option<App>> {
-        let mut conn = db_global_init()?;
-        let res = conn
-            .exec_first(
-                "select * from stserver.app where id=:id",
-                params! {
-                    "id" => id,
-                },
-            )
-            .map(|row| {
-                row.map(|(id, name, description, certs)| App {
-                    id: id,
-                    name: name,
-                    description: description,
-                    certs: certs,
-                })
-            });
-
-        Ok(res?)
+    fn from_row(mut row: Row) -> error::Result<App> {
+        Ok(App {
+            id: column(&mut row, "id")?,
+            name: column(&mut row, "name")?,
+            description: column(&mut row, "description")?,
+            certs: column(&mut row, "certs")?,
+            certs_passwd: column(&mut row, "certs_passwd")?,
+            prev_certs: column(&mut row, "prev_certs")?,
+            prev_certs_passwd: column(&mut row, "prev_certs_passwd")?,
+        })
+    }
+
+    pub async fn get(id: usize) -> error::Result<Option<App>> {
+        mysql_pool::pool()?
+            .run(move |conn| {
+                let row: Option<Row> = conn.exec_first(
+                    "select id, name, description, certs, certs_passwd, prev_certs, prev_certs_passwd \
+                     from app where id=:id",
+                    params! {
+                        "id" => id,
+                    },
+                )?;
+                row.map(App::from_row).transpose()
+            })
+            .await
+    }
+
+    pub async fn list() -> error::Result<Vec<App>> {
+        mysql_pool::pool()?
+            .run(|conn| {
+                let rows: Vec<Row> = conn.query(
+                    "select id, name, description, certs, certs_passwd, prev_certs, prev_certs_passwd \
+                     from app order by id",
+                )?;
+                rows.into_iter().map(App::from_row).collect()
+            })
+            .await
+    }
+
+    // 返回新项目 id
+    pub async fn insert(name: &str, description: Option<&str>) -> error::Result<usize> {
+        let name = name.to_string();
+        let description = description.map(|description| description.to_string());
+        mysql_pool::pool()?
+            .run(move |conn| {
+                conn.exec_drop(
+                    "insert into app(name, description) values (:name, :description)",
+                    params! {
+                        "name" => name,
+                        "description" => description,
+                    },
+                )?;
+                Ok(conn.last_insert_id() as usize)
+            })
+            .await
+    }
+
+    /*
+       certs_passwd 为主密钥加密后的密码
+       原证书库移到 prev_certs，mysql 按顺序赋值，prev_certs 取到的是更新前的值
+    */
+    pub async fn set_keystore(id: usize, certs: &[u8], certs_passwd: &str) -> error::Result<()> {
+        let certs = certs.to_vec();
+        let certs_passwd = certs_passwd.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec_drop(
+                    "update app set prev_certs=certs, prev_certs_passwd=certs_passwd, \
+                     certs=:certs, certs_passwd=:certs_passwd where id=:id",
+                    params! {
+                        "id" => id,
+                        "certs" => certs,
+                        "certs_passwd" => certs_passwd,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    // 原地更新证书库密码，用于加密历史数据
+    pub async fn update_passwd(
+        id: usize,
+        certs_passwd: Option<&str>,
+        prev_certs_passwd: Option<&str>,
+    ) -> error::Result<()> {
+        let certs_passwd = certs_passwd.map(str::to_string);
+        let prev_certs_passwd = prev_certs_passwd.map(str::to_string);
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec_drop(
+                    "update app set certs_passwd=:certs_passwd, \
+                     prev_certs_passwd=:prev_certs_passwd where id=:id",
+                    params! {
+                        "id" => id,
+                        "certs_passwd" => certs_passwd,
+                        "prev_certs_passwd" => prev_certs_passwd,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    // 轮换完成后删除上一版证书库
+    pub async fn retire_keystore(id: usize) -> error::Result<()> {
+        mysql_pool::pool()?
+            .run(move |conn| {
+                Ok(conn.exec_drop(
+                    "update app set prev_certs=null, prev_certs_passwd=null where id=:id",
+                    params! {
+                        "id" => id,
+                    },
+                )?)
+            })
+            .await
     }
 }
 
+/*
+   网关接口配置
+   load_balance 负载策略 0轮询/1IP-Hash/2随机
+*/
+pub struct GatewayApi {
+    pub app_id: usize,
+    pub api_name: String,
+    // 主机间使用;间隔
+    pub hosts: String,
+    pub req_path: String,
+    pub req_method: Option<String>,
+    // 0表示不限制
+    pub threshold_sec: Option<i32>,
+    pub data_req_example: Option<String>,
+    pub data_resp_example: Option<String>,
+    // 1 enable/0 disable
+    pub use_state: Option<i32>,
+    pub load_balance: Option<i32>,
+}
+
+impl GatewayApi {
+    fn from_row(mut row: Row) -> error::Result<GatewayApi> {
+        Ok(GatewayApi {
+            app_id: column(&mut row, "app_id")?,
+            api_name: column(&mut row, "api_name")?,
+            hosts: column(&mut row, "hosts")?,
+            req_path: column(&mut row, "req_path")?,
+            req_method: column(&mut row, "req_method")?,
+            threshold_sec: column(&mut row, "threshold_sec")?,
+            data_req_example: column(&mut row, "data_req_example")?,
+            data_resp_example: column(&mut row, "data_resp_example")?,
+            use_state: column(&mut row, "use_state")?,
+            load_balance: column(&mut row, "load_balance")?,
+        })
+    }
+
+    pub async fn get(app_id: usize, api_name: &str) -> error::Result<Option<GatewayApi>> {
+        let api_name = api_name.to_string();
+        mysql_pool::pool()?
+            .run(move |conn| {
+                let row: Option<Row> = conn.exec_first(
+                    "select app_id, api_name, hosts, req_path, req_method, threshold_sec, \
+                     data_req_example, data_resp_example, use_state, load_balance \
+                     from gateway_api where app_id=:app_id and api_name=:api_name",
+                    params! {
+                        "app_id" => app_id,
+                        "api_name" => api_name,
+                    },
+                )?;
+                row.map(GatewayApi::from_row).transpose()
+            })
+            .await
+    }
+
+    pub fn host_list(&self) -> Vec<String> {
+        self.hosts
+            .split(';')
+            .map(|host| host.trim())
+            .filter(|host| !host.is_empty())
+            .map(|host| host.to_string())
+            .collect()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.use_state.unwrap_or(1) == 1
+    }
+}
@@ -0,0 +1,123 @@
+/*
+   客户端吊销名单
+   keys revoke 先在密钥仓库标记吊销(status/revoked_reason/revoked_at)，再加入 redis 集合
+   握手和读取会话时只检查进程内名单，不访问网络
+   后台任务定时同步：配置了 redis 时读取 redis 集合，未配置或者 redis 不可用时读取密钥仓库
+   keys delete 删除密钥记录后从 redis 集合移除，同一 serialid 可以重新创建
+   启动时从密钥仓库加载已吊销的 serialid 并写入 redis 集合
+*/
+
+use std::{collections::HashSet, time::Duration};
+
+use redis::AsyncCommands;
+
+use super::{mem, redis_pool, repository};
+use crate::{config, error::Result};
+
+const REDIS_KEY: &str = "stserver:denylist";
+
+pub async fn init() -> Result<()> {
+    let serialids = repository::repository()?.revoked_serialids().await?;
+    println!("denylist loaded {} revoked clients", serialids.len());
+    if !serialids.is_empty() {
+        if let Ok(pool) = redis_pool::pool() {
+            let members = serialids.clone();
+            if let Err(err) = pool
+                .run(|mut conn| async move { conn.sadd::<_, _, ()>(REDIS_KEY, members).await })
+                .await
+            {
+                println!("denylist redis error: {:?}", err);
+            }
+        }
+    }
+    mem::DENYLIST.write().unwrap().extend(serialids);
+    Ok(())
+}
+
+/*
+   启动名单同步任务，未配置 [denylist] 时使用默认值
+*/
+pub fn spawn_sync(config: &config::Config) {
+    let default = config::Denylist::default();
+    let denylist = config.denylist.as_ref().unwrap_or(&default);
+    if denylist.sync_interval_secs == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(denylist.sync_interval_secs);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match load().await {
+                // 整体替换，已删除的 serialid 随同步移出名单
+                Ok(serialids) => *mem::DENYLIST.write().unwrap() = serialids,
+                Err(err) => println!("denylist sync error: {:?}", err),
+            }
+        }
+    });
+}
+
+async fn load() -> Result<HashSet<String>> {
+    if let Ok(pool) = redis_pool::pool() {
+        match pool
+            .run(|mut conn| async move { conn.smembers(REDIS_KEY).await })
+            .await
+        {
+            Ok(serialids) => return Ok(serialids),
+            Err(err) => println!("denylist redis error, load from repository: {:?}", err),
+        }
+    }
+    let serialids = repository::repository()?.revoked_serialids().await?;
+    Ok(serialids.into_iter().collect())
+}
+
+/*
+   加入名单，配置了 redis 时写入失败返回错误，其他节点依赖 redis 集合获知吊销
+*/
+pub async fn add(serialid: &str) -> Result<()> {
+    mem::DENYLIST.write().unwrap().insert(serialid.to_string());
+    if let Ok(pool) = redis_pool::pool() {
+        let serialid = serialid.to_string();
+        pool.run(|mut conn| async move { conn.sadd::<_, _, ()>(REDIS_KEY, serialid).await })
+            .await?;
+    }
+    Ok(())
+}
+
+/*
+   移出名单，配置了 redis 时写入失败返回错误，否则其他节点下次同步仍会拒绝该 serialid
+*/
+pub async fn remove(serialid: &str) -> Result<()> {
+    mem::DENYLIST.write().unwrap().remove(serialid);
+    if let Ok(pool) = redis_pool::pool() {
+        let serialid = serialid.to_string();
+        pool.run(|mut conn| async move { conn.srem::<_, _, ()>(REDIS_KEY, serialid).await })
+            .await?;
+    }
+    Ok(())
+}
+
+pub fn contains(serialid: &str) -> bool {
+    mem::DENYLIST.read().unwrap().contains(serialid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn add_without_redis() {
+        assert!(!contains("denylist-test"));
+        add("denylist-test").await.unwrap();
+        assert!(contains("denylist-test"));
+    }
+
+    #[tokio::test]
+    async fn revoke_delete_recreate() {
+        add("denylist-recreate").await.unwrap();
+        assert!(contains("denylist-recreate"));
+        // keys delete 之后重新创建的同名 serialid 不再被拒绝
+        remove("denylist-recreate").await.unwrap();
+        assert!(!contains("denylist-recreate"));
+    }
+}
@@ -0,0 +1,293 @@
+/*
+   项目和客户端密钥读缓存
+   读取顺序 进程内 LRU -> redis -> 密钥仓库，下层命中后回写上层
+   redis 不可用时直接读密钥仓库，不影响握手
+   修改 app、app_client_key 后调用 invalidate_* 清除缓存，
+   其他节点的进程内缓存在 local_ttl_secs 后失效
+*/
+
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    db::{App, AppClientKey},
+    mem, redis_pool, repository,
+};
+use crate::{config, error::Result};
+
+const REDIS_KEY_PREFIX: &str = "stserver:cache";
+
+pub struct LocalCache<V> {
+    entries: Mutex<lru::LruCache<String, (V, Instant)>>,
+    ttl: Duration,
+}
+
+impl<V: Clone> LocalCache<V> {
+    pub fn new(capacity: usize, ttl: Duration) -> LocalCache<V> {
+        LocalCache {
+            entries: Mutex::new(lru::LruCache::new(
+                NonZeroUsize::new(capacity.max(1)).unwrap(),
+            )),
+            ttl: ttl,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((value, deadline)) if *deadline > now => return Some(value.clone()),
+            Some(_) => {}
+            None => return None,
+        }
+        entries.pop(key);
+        None
+    }
+
+    pub fn put(&self, key: &str, value: V) {
+        self.entries
+            .lock()
+            .unwrap()
+            .put(key.to_string(), (value, Instant::now() + self.ttl));
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().pop(key);
+    }
+}
+
+/*
+   单类数据的分层缓存，name 区分 redis 中的键
+*/
+pub struct ReadThrough<V> {
+    name: &'static str,
+    local: LocalCache<V>,
+    redis_ttl: Duration,
+}
+
+impl<V> ReadThrough<V>
+where
+    V: Clone + Serialize + DeserializeOwned,
+{
+    pub fn new(name: &'static str, config: &config::Cache) -> ReadThrough<V> {
+        ReadThrough {
+            name: name,
+            local: LocalCache::new(config.capacity, Duration::from_secs(config.local_ttl_secs)),
+            redis_ttl: Duration::from_secs(config.redis_ttl_secs),
+        }
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        format!("{}:{}:{}", REDIS_KEY_PREFIX, self.name, key)
+    }
+
+    fn redis_pool(&self) -> Option<Arc<redis_pool::RedisPool>> {
+        match self.redis_ttl.as_secs() {
+            0 => None,
+            _ => redis_pool::pool().ok(),
+        }
+    }
+
+    /*
+       依次查询本地缓存、redis，都未命中时调用 load 读取密钥仓库
+       查询结果为空时不缓存
+    */
+    pub async fn get<F, Fut>(&self, key: &str, load: F) -> Result<Option<V>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<V>>>,
+    {
+        if let Some(value) = self.local.get(key) {
+            return Ok(Some(value));
+        }
+        let redis_key = self.redis_key(key);
+        let pool = self.redis_pool();
+        if let Some(pool) = &pool {
+            let cached: Result<Option<String>> = pool
+                .run(|mut conn| async move { conn.get(redis_key.as_str()).await })
+                .await;
+            match cached {
+                Ok(Some(cached)) => match serde_json::from_str::<V>(&cached) {
+                    Ok(value) => {
+                        self.local.put(key, value.clone());
+                        return Ok(Some(value));
+                    }
+                    Err(err) => println!("cache {} decode error: {:?}", self.name, err),
+                },
+                Ok(None) => {}
+                Err(err) => println!("cache {} redis error: {:?}", self.name, err),
+            }
+        }
+
+        let value = match load().await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        self.local.put(key, value.clone());
+        if let Some(pool) = &pool {
+            let redis_key = self.redis_key(key);
+            let ttl = self.redis_ttl.as_secs();
+            let cached = serde_json::to_string(&value)?;
+            let res: Result<()> = pool
+                .run(|mut conn| async move { conn.set_ex(redis_key.as_str(), cached, ttl).await })
+                .await;
+            if let Err(err) = res {
+                println!("cache {} redis write error: {:?}", self.name, err);
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub async fn invalidate(&self, key: &str) -> Result<()> {
+        self.local.remove(key);
+        if let Some(pool) = self.redis_pool() {
+            let redis_key = self.redis_key(key);
+            pool.run(|mut conn| async move { conn.del::<_, ()>(redis_key.as_str()).await })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+pub struct KeyCache {
+    apps: ReadThrough<App>,
+    // 同一 serialid 的全部密钥版本
+    client_keys: ReadThrough<Vec<AppClientKey>>,
+}
+
+impl KeyCache {
+    pub fn new(config: &config::Cache) -> KeyCache {
+        KeyCache {
+            apps: ReadThrough::new("app", config),
+            client_keys: ReadThrough::new("app_client_key_versions", config),
+        }
+    }
+}
+
+/*
+   根据 [cache] 配置初始化，未配置时使用默认值
+*/
+pub fn init(config: &config::Config) {
+    let cache = match &config.cache {
+        Some(cache) => KeyCache::new(cache),
+        None => KeyCache::new(&config::Cache::default()),
+    };
+    *mem::KEY_CACHE.write().unwrap() = Some(Arc::new(cache));
+}
+
+// 未初始化时直接读密钥仓库
+fn key_cache() -> Option<Arc<KeyCache>> {
+    mem::KEY_CACHE.read().unwrap().clone()
+}
+
+pub async fn app(id: usize) -> Result<Option<App>> {
+    match key_cache() {
+        Some(cache) => {
+            cache
+                .apps
+                .get(&id.to_string(), || async move {
+                    repository::repository()?.app(id).await
+                })
+                .await
+        }
+        None => repository::repository()?.app(id).await,
+    }
+}
+
+// 客户端全部密钥版本，新版本在前
+pub async fn client_key_versions(serialid: &str) -> Result<Vec<AppClientKey>> {
+    match key_cache() {
+        Some(cache) => Ok(cache
+            .client_keys
+            .get(serialid, || async move {
+                let versions = repository::repository()?
+                    .client_key_versions(serialid)
+                    .await?;
+                Ok(Some(versions).filter(|versions| !versions.is_empty()))
+            })
+            .await?
+            .unwrap_or_default()),
+        None => {
+            repository::repository()?
+                .client_key_versions(serialid)
+                .await
+        }
+    }
+}
+
+pub async fn invalidate_app(id: usize) -> Result<()> {
+    match key_cache() {
+        Some(cache) => cache.apps.invalidate(&id.to_string()).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn invalidate_client_key(serialid: &str) -> Result<()> {
+    match key_cache() {
+        Some(cache) => cache.client_keys.invalidate(serialid).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_cache() {
+        let cache = LocalCache::new(2, Duration::from_secs(10));
+        cache.put("a", 1);
+        cache.put("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+        // 容量满时淘汰最久未使用的 b
+        cache.put("c", 3);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(3));
+
+        assert_eq!(
+            cache.get_at("a", Instant::now() + Duration::from_secs(11)),
+            None
+        );
+        assert_eq!(cache.get("a"), None);
+
+        cache.remove("c");
+        assert_eq!(cache.get("c"), None);
+    }
+
+    #[tokio::test]
+    async fn read_through_without_redis() {
+        let cache: ReadThrough<String> = ReadThrough::new(
+            "test",
+            &config::Cache {
+                capacity: 8,
+                local_ttl_secs: 60,
+                redis_ttl_secs: 0,
+            },
+        );
+        let value = cache
+            .get("k", || async { Ok(Some("v".to_string())) })
+            .await
+            .unwrap();
+        assert_eq!(value, Some("v".to_string()));
+        // 命中本地缓存，不再调用 load
+        let value = cache
+            .get("k", || async { panic!("should not load") })
+            .await
+            .unwrap();
+        assert_eq!(value, Some("v".to_string()));
+
+        cache.invalidate("k").await.unwrap();
+        let value = cache.get("k", || async { Ok(None) }).await.unwrap();
+        assert_eq!(value, None);
+    }
+}
@@ -9,11 +9,15 @@
 
 use lazy_static;
 use mysql::PooledConn;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 
+use super::keycache::KeyCache;
+use super::mysql_pool::MysqlPool;
+use super::redis_pool::RedisPool;
+use super::repository::KeyRepository;
+use super::session::SessionStore;
 use crate::config::Config;
-use mysql::Pool;
 
 /*
    存储一些临时变量
@@ -40,6 +44,15 @@ lazy_static::lazy_static! {
         m
     });
     pub static ref CONFIG: Mutex<Config> = Mutex::new(Config::default());
-    pub static ref MYSQL_POOL: Mutex<Option<Pool>> = Mutex::new(None);
+    pub static ref MYSQL_POOL: RwLock<Option<Arc<MysqlPool>>> = RwLock::new(None);
+    pub static ref REDIS_POOL: RwLock<Option<Arc<RedisPool>>> = RwLock::new(None);
+    pub static ref SESSION_STORE: RwLock<Option<Arc<dyn SessionStore>>> = RwLock::new(None);
+    pub static ref KEY_REPOSITORY: RwLock<Option<Arc<dyn KeyRepository>>> = RwLock::new(None);
+    pub static ref MASTER_KEY: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+    // 是否接受未加密的历史密钥材料，配置主密钥后默认拒绝
+    pub static ref ALLOW_PLAINTEXT_KEY: RwLock<bool> = RwLock::new(true);
+    pub static ref KEY_CACHE: RwLock<Option<Arc<KeyCache>>> = RwLock::new(None);
+    // 已吊销的客户端 serialid
+    pub static ref DENYLIST: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
 
 }
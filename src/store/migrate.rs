@@ -0,0 +1,531 @@
+/*
+   数据库结构迁移
+   迁移脚本按数据库类型放在 scripts/db/migrations/<mysql|postgres|sqlite>，编译时嵌入
+   已执行的版本记录在 schema_migrations 表中，up 按版本顺序执行未执行的脚本
+   新增迁移只能追加版本，已发布的脚本不能修改
+   mysql 的 DDL 不能回滚，逐条执行并在 schema_migration_statements 记录已执行的语句，
+   中途失败后重新执行时跳过已完成的语句，全部完成后写入 schema_migrations
+*/
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mysql::{params, prelude::Queryable};
+
+use super::mysql_pool;
+use crate::{
+    config,
+    error::{Error, ErrorKind, Result},
+};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub(crate) sql: &'static str,
+}
+
+pub struct AppliedMigration {
+    pub version: u32,
+    // unix 时间戳秒
+    pub applied_at: i64,
+}
+
+const MYSQL_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("../../scripts/db/migrations/mysql/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "sealed_keys",
+        sql: include_str!("../../scripts/db/migrations/mysql/0002_sealed_keys.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "keystore_rotation",
+        sql: include_str!("../../scripts/db/migrations/mysql/0003_keystore_rotation.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "client_key_versions",
+        sql: include_str!("../../scripts/db/migrations/mysql/0004_client_key_versions.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "client_key_revocation",
+        sql: include_str!("../../scripts/db/migrations/mysql/0005_client_key_revocation.sql"),
+    },
+];
+
+const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("../../scripts/db/migrations/postgres/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "sealed_keys",
+        sql: include_str!("../../scripts/db/migrations/postgres/0002_sealed_keys.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "keystore_rotation",
+        sql: include_str!("../../scripts/db/migrations/postgres/0003_keystore_rotation.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "client_key_versions",
+        sql: include_str!("../../scripts/db/migrations/postgres/0004_client_key_versions.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "client_key_revocation",
+        sql: include_str!("../../scripts/db/migrations/postgres/0005_client_key_revocation.sql"),
+    },
+];
+
+pub(crate) const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("../../scripts/db/migrations/sqlite/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "sealed_keys",
+        sql: include_str!("../../scripts/db/migrations/sqlite/0002_sealed_keys.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "keystore_rotation",
+        sql: include_str!("../../scripts/db/migrations/sqlite/0003_keystore_rotation.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "client_key_versions",
+        sql: include_str!("../../scripts/db/migrations/sqlite/0004_client_key_versions.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "client_key_revocation",
+        sql: include_str!("../../scripts/db/migrations/sqlite/0005_client_key_revocation.sql"),
+    },
+];
+
+#[async_trait]
+trait Migrator: Send + Sync {
+    fn migrations(&self) -> &'static [Migration];
+    // 创建 schema_migrations
+    async fn prepare(&self) -> Result<()>;
+    async fn applied(&self) -> Result<Vec<AppliedMigration>>;
+    // 执行脚本并记录版本
+    async fn apply(&self, migration: &'static Migration) -> Result<()>;
+}
+
+/*
+   执行所有未执行的迁移，返回本次执行的版本
+*/
+pub async fn up(config: &config::Config) -> Result<Vec<u32>> {
+    let migrator = migrator(config).await?;
+    migrator.prepare().await?;
+    let applied = migrator.applied().await?;
+    let mut versions = vec![];
+    for migration in pending(migrator.migrations(), &applied) {
+        migrator.apply(migration).await?;
+        versions.push(migration.version);
+    }
+    Ok(versions)
+}
+
+/*
+   所有迁移及其执行时间，未执行的为 None
+*/
+pub async fn status(
+    config: &config::Config,
+) -> Result<Vec<(&'static Migration, Option<AppliedMigration>)>> {
+    let migrator = migrator(config).await?;
+    migrator.prepare().await?;
+    let mut applied = migrator.applied().await?;
+    Ok(migrator
+        .migrations()
+        .iter()
+        .map(|migration| {
+            let record = applied
+                .iter()
+                .position(|record| record.version == migration.version)
+                .map(|index| applied.remove(index));
+            (migration, record)
+        })
+        .collect())
+}
+
+fn pending(
+    migrations: &'static [Migration],
+    applied: &[AppliedMigration],
+) -> Vec<&'static Migration> {
+    migrations
+        .iter()
+        .filter(|migration| {
+            !applied
+                .iter()
+                .any(|record| record.version == migration.version)
+        })
+        .collect()
+}
+
+async fn migrator(config: &config::Config) -> Result<Box<dyn Migrator>> {
+    let backend = match &config.repository {
+        Some(repository) => repository.backend.as_str(),
+        None => "mysql",
+    };
+    match (backend, &config.repository) {
+        ("mysql", _) => {
+            match &config.mysql {
+                Some(mysql) => mysql_pool::init(mysql).await?,
+                None => return Err(Error::new(ErrorKind::CONFIG, "missing [mysql] config")),
+            }
+            Ok(Box::new(MysqlMigrator))
+        }
+        ("postgres", Some(repository)) => {
+            Ok(Box::new(PostgresMigrator::connect(repository).await?))
+        }
+        ("sqlite", Some(repository)) => match &repository.path {
+            Some(path) => Ok(Box::new(SqliteMigrator::open(Path::new(path))?)),
+            None => Err(Error::new(
+                ErrorKind::CONFIG,
+                "sqlite repository requires path",
+            )),
+        },
+        _ => Err(Error::new(
+            ErrorKind::CONFIG,
+            "repository backend has no database schema",
+        )),
+    }
+}
+
+/*
+   按分号拆分 mysql 脚本，跳过注释和字符串中的分号
+*/
+fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        current.push(c);
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                while let Some(c) = chars.next() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '\'' | '"' => {
+                current.push(c);
+                while let Some(quoted) = chars.next() {
+                    current.push(quoted);
+                    if quoted == c {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                if !current.trim().is_empty() {
+                    statements.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() {
+        statements.push(current.trim().to_string());
+    }
+    statements
+}
+
+struct MysqlMigrator;
+
+#[async_trait]
+impl Migrator for MysqlMigrator {
+    fn migrations(&self) -> &'static [Migration] {
+        MYSQL_MIGRATIONS
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        mysql_pool::pool()?
+            .run(|conn| {
+                conn.query_drop(
+                    "create table if not exists schema_migrations(
+                        version int unsigned primary key,
+                        name varchar(100) not null,
+                        applied_at bigint not null
+                    ) default charset utf8",
+                )?;
+                Ok(conn.query_drop(
+                    "create table if not exists schema_migration_statements(
+                        version int unsigned not null,
+                        statement int unsigned not null,
+                        applied_at bigint not null,
+                        primary key (version, statement)
+                    ) default charset utf8",
+                )?)
+            })
+            .await
+    }
+
+    async fn applied(&self) -> Result<Vec<AppliedMigration>> {
+        mysql_pool::pool()?
+            .run(|conn| {
+                Ok(conn.query_map(
+                    "select version, applied_at from schema_migrations order by version",
+                    |(version, applied_at)| AppliedMigration {
+                        version: version,
+                        applied_at: applied_at,
+                    },
+                )?)
+            })
+            .await
+    }
+
+    async fn apply(&self, migration: &'static Migration) -> Result<()> {
+        mysql_pool::pool()?
+            .run(move |conn| {
+                // mysql 的 DDL 隐式提交，无法放在事务中，逐条记录进度
+                let done: Vec<u32> = conn.exec(
+                    "select statement from schema_migration_statements where version=:version",
+                    params! {
+                        "version" => migration.version,
+                    },
+                )?;
+                for (index, statement) in split_statements(migration.sql).into_iter().enumerate() {
+                    let index = index as u32;
+                    if done.contains(&index) {
+                        continue;
+                    }
+                    conn.query_drop(statement)?;
+                    conn.exec_drop(
+                        "insert into schema_migration_statements(version, statement, applied_at) \
+                         values (:version, :statement, :applied_at)",
+                        params! {
+                            "version" => migration.version,
+                            "statement" => index,
+                            "applied_at" => Utc::now().timestamp(),
+                        },
+                    )?;
+                }
+                conn.exec_drop(
+                    "insert into schema_migrations(version, name, applied_at) values (:version, :name, :applied_at)",
+                    params! {
+                        "version" => migration.version,
+                        "name" => migration.name,
+                        "applied_at" => Utc::now().timestamp(),
+                    },
+                )?;
+                Ok(conn.exec_drop(
+                    "delete from schema_migration_statements where version=:version",
+                    params! {
+                        "version" => migration.version,
+                    },
+                )?)
+            })
+            .await
+    }
+}
+
+struct PostgresMigrator {
+    client: tokio::sync::Mutex<tokio_postgres::Client>,
+}
+
+impl PostgresMigrator {
+    async fn connect(config: &config::Repository) -> Result<PostgresMigrator> {
+        let url = match &config.url {
+            Some(url) => url,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::CONFIG,
+                    "postgres repository requires url",
+                ))
+            }
+        };
+        let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                println!("postgres connection error: {:?}", err);
+            }
+        });
+        Ok(PostgresMigrator {
+            client: tokio::sync::Mutex::new(client),
+        })
+    }
+}
+
+#[async_trait]
+impl Migrator for PostgresMigrator {
+    fn migrations(&self) -> &'static [Migration] {
+        POSTGRES_MIGRATIONS
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .batch_execute(
+                "create table if not exists schema_migrations(
+                    version integer primary key,
+                    name varchar(100) not null,
+                    applied_at bigint not null
+                )",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn applied(&self) -> Result<Vec<AppliedMigration>> {
+        let rows = self
+            .client
+            .lock()
+            .await
+            .query(
+                "select version, applied_at from schema_migrations order by version",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| AppliedMigration {
+                version: row.get::<_, i32>("version") as u32,
+                applied_at: row.get("applied_at"),
+            })
+            .collect())
+    }
+
+    async fn apply(&self, migration: &'static Migration) -> Result<()> {
+        let mut client = self.client.lock().await;
+        // postgres 的 DDL 支持事务，失败时整体回滚
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "insert into schema_migrations(version, name, applied_at) values ($1, $2, $3)",
+                &[
+                    &(migration.version as i32),
+                    &migration.name,
+                    &Utc::now().timestamp(),
+                ],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+struct SqliteMigrator {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+}
+
+impl SqliteMigrator {
+    fn open(path: &Path) -> Result<SqliteMigrator> {
+        Ok(SqliteMigrator {
+            conn: std::sync::Mutex::new(rusqlite::Connection::open(path)?),
+        })
+    }
+}
+
+// 迁移是一次性命令，sqlite 直接在当前线程执行
+#[async_trait]
+impl Migrator for SqliteMigrator {
+    fn migrations(&self) -> &'static [Migration] {
+        SQLITE_MIGRATIONS
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        self.conn.lock().unwrap().execute_batch(
+            "create table if not exists schema_migrations(
+                version integer primary key,
+                name text not null,
+                applied_at integer not null
+            )",
+        )?;
+        Ok(())
+    }
+
+    async fn applied(&self) -> Result<Vec<AppliedMigration>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("select version, applied_at from schema_migrations order by version")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AppliedMigration {
+                version: row.get("version")?,
+                applied_at: row.get("applied_at")?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<AppliedMigration>>>()?)
+    }
+
+    async fn apply(&self, migration: &'static Migration) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(migration.sql)?;
+        transaction.execute(
+            "insert into schema_migrations(version, name, applied_at) values (?1, ?2, ?3)",
+            rusqlite::params![migration.version, migration.name, Utc::now().timestamp()],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn versions_ordered() {
+        for migrations in &[MYSQL_MIGRATIONS, POSTGRES_MIGRATIONS, SQLITE_MIGRATIONS] {
+            assert!(migrations
+                .windows(2)
+                .all(|pair| pair[0].version < pair[1].version));
+        }
+        assert_eq!(MYSQL_MIGRATIONS.len(), POSTGRES_MIGRATIONS.len());
+        assert_eq!(MYSQL_MIGRATIONS.len(), SQLITE_MIGRATIONS.len());
+    }
+
+    #[test]
+    fn split_mysql() {
+        let statements = split_statements(
+            "/* a; b */ create table t(a int, -- x;y\n b varchar(5) default ';');\n\ninsert into t values (1, 'c');",
+        );
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].ends_with("default ';')"));
+        assert_eq!(statements[1], "insert into t values (1, 'c')");
+        assert_eq!(split_statements(MYSQL_MIGRATIONS[0].sql).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn sqlite_up() {
+        let migrator = SqliteMigrator {
+            conn: std::sync::Mutex::new(rusqlite::Connection::open_in_memory().unwrap()),
+        };
+        migrator.prepare().await.unwrap();
+        for migration in pending(migrator.migrations(), &migrator.applied().await.unwrap()) {
+            migrator.apply(migration).await.unwrap();
+        }
+        let applied = migrator.applied().await.unwrap();
+        assert_eq!(applied.len(), SQLITE_MIGRATIONS.len());
+        assert!(pending(migrator.migrations(), &applied).is_empty());
+    }
+}
@@ -1,3 +1,12 @@
 pub mod cache;
+pub mod certpool;
 pub mod db;
+pub mod denylist;
+pub mod envelope;
+pub mod keycache;
 pub mod mem;
+pub mod migrate;
+pub mod mysql_pool;
+pub mod redis_pool;
+pub mod repository;
+pub mod session;
@@ -0,0 +1,98 @@
+/*
+   mysql 连接池
+   mysql 驱动是同步阻塞的，查询放到 tokio 阻塞线程池中执行，不占用异步工作线程
+   启动时建立连接池，每个查询都有超时，包括等待空闲连接的时间
+*/
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use mysql::{OptsBuilder, Pool, PooledConn};
+
+use super::mem;
+use crate::{
+    config,
+    error::{Error, ErrorKind, Result},
+};
+
+pub struct MysqlPool {
+    pool: Pool,
+    query_timeout: Duration,
+}
+
+impl MysqlPool {
+    pub async fn connect(config: &config::Mysql) -> Result<MysqlPool> {
+        let connect_timeout = Duration::from_secs(config.connect_timeout_secs);
+        let query_timeout = Duration::from_secs(config.query_timeout_secs);
+        let opts = OptsBuilder::new()
+            .ip_or_hostname(Some(config.host.clone()))
+            .tcp_port(config.port as u16)
+            .user(Some(config.user.clone()))
+            .pass(Some(config.passwd.clone()))
+            .db_name(Some(config.db_name.clone()))
+            .tcp_connect_timeout(Some(connect_timeout))
+            .read_timeout(Some(query_timeout))
+            .write_timeout(Some(query_timeout));
+        let (min, max) = (config.pool_min, config.pool_max.max(1));
+        // 建立最小连接数时同样阻塞
+        let pool = blocking(connect_timeout * min.max(1) as u32, move || {
+            Ok(Pool::new_manual(min.min(max), max, opts)?)
+        })
+        .await?;
+        Ok(MysqlPool {
+            pool: pool,
+            query_timeout: query_timeout,
+        })
+    }
+
+    /*
+       在阻塞线程池中执行查询
+       let app = pool.run(move |conn| Ok(conn.exec_first(...)?)).await?;
+    */
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut PooledConn) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        let acquire_timeout = self.query_timeout.as_millis() as u32;
+        blocking(self.query_timeout, move || {
+            let mut conn = pool.try_get_conn(acquire_timeout)?;
+            f(&mut conn)
+        })
+        .await
+    }
+}
+
+/*
+   超时后调用方立即返回，阻塞线程中的查询由 read_timeout 结束
+*/
+fn blocking<T, F>(timeout: Duration, f: F) -> impl Future<Output = Result<T>>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let task = tokio::task::spawn_blocking(f);
+    async move {
+        match tokio::time::timeout(timeout, task).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(err)) => Err(Error::new(ErrorKind::MYSQL, &err.to_string())),
+            Err(_) => Err(Error::new(ErrorKind::MYSQL, "mysql query timeout").with_retry_after(1)),
+        }
+    }
+}
+
+pub async fn init(config: &config::Mysql) -> Result<()> {
+    let pool = MysqlPool::connect(config).await?;
+    *mem::MYSQL_POOL.write().unwrap() = Some(Arc::new(pool));
+    Ok(())
+}
+
+pub fn pool() -> Result<Arc<MysqlPool>> {
+    match &*mem::MYSQL_POOL.read().unwrap() {
+        Some(pool) => Ok(pool.clone()),
+        None => Err(Error::new(
+            ErrorKind::CONFIG,
+            "mysql pool not initialized, check [mysql] config",
+        )),
+    }
+}
@@ -0,0 +1,325 @@
+/*
+   redis 异步连接池
+   1 single 单节点，启动时建立 pool_size 个 ConnectionManager 轮询使用，连接断开后下一次请求时自动重连
+   2 cluster 集群，由种子节点发现拓扑，请求按 slot 路由，MOVED/ASK 自动重定向
+   3 sentinel 哨兵，向哨兵查询 master 地址，连接断开或者 master 降级为只读后重新查询
+   每个请求都有响应超时，会话存储等上层逻辑不感知部署模式
+*/
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use redis::{
+    aio::{ConnectionLike, ConnectionManager, MultiplexedConnection},
+    cluster::ClusterClientBuilder,
+    cluster_async::ClusterConnection,
+    sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType},
+    Client, Cmd, ConnectionAddr, ConnectionInfo, IntoConnectionInfo, Pipeline, RedisError,
+    RedisFuture, RedisResult, TlsMode, Value,
+};
+
+use super::mem;
+use crate::{
+    config,
+    error::{Error, ErrorKind, Result},
+};
+
+/*
+   与部署模式无关的连接，可直接使用 redis::AsyncCommands
+*/
+#[derive(Clone)]
+pub enum RedisConnection {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+    Sentinel(MultiplexedConnection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            RedisConnection::Single(conn) => conn.req_packed_command(cmd),
+            RedisConnection::Cluster(conn) => conn.req_packed_command(cmd),
+            RedisConnection::Sentinel(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            RedisConnection::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            RedisConnection::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+            RedisConnection::Sentinel(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConnection::Single(conn) => conn.get_db(),
+            RedisConnection::Cluster(conn) => conn.get_db(),
+            RedisConnection::Sentinel(conn) => conn.get_db(),
+        }
+    }
+}
+
+enum Topology {
+    Single,
+    Cluster,
+    Sentinel(tokio::sync::Mutex<SentinelClient>),
+}
+
+pub struct RedisPool {
+    conns: RwLock<Vec<RedisConnection>>,
+    next: AtomicUsize,
+    topology: Topology,
+    connect_timeout: Duration,
+    response_timeout: Duration,
+}
+
+impl RedisPool {
+    pub async fn connect(config: &config::Redis) -> Result<RedisPool> {
+        let connect_timeout = Duration::from_secs(config.connect_timeout_secs);
+        let response_timeout = Duration::from_secs(config.response_timeout_secs);
+        let size = config.pool_size.max(1);
+        let mut conns = Vec::with_capacity(size);
+        let topology = match config.mode.as_str() {
+            "single" => {
+                let client = Client::open(connection_info(config, &config.url)?)?;
+                for _ in 0..size {
+                    let conn =
+                        with_timeout(connect_timeout, ConnectionManager::new(client.clone()))
+                            .await?;
+                    conns.push(RedisConnection::Single(conn));
+                }
+                Topology::Single
+            }
+            "cluster" => {
+                let nodes = seed_nodes(config)
+                    .iter()
+                    .map(|node| connection_info(config, node))
+                    .collect::<Result<Vec<ConnectionInfo>>>()?;
+                let client = ClusterClientBuilder::new(nodes)
+                    .connection_timeout(connect_timeout)
+                    .response_timeout(response_timeout)
+                    .build()?;
+                for _ in 0..size {
+                    let conn = with_timeout(connect_timeout, client.get_async_connection()).await?;
+                    conns.push(RedisConnection::Cluster(conn));
+                }
+                Topology::Cluster
+            }
+            "sentinel" => {
+                if config.sentinel_master.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::CONFIG,
+                        "redis sentinel mode requires sentinel_master",
+                    ));
+                }
+                let mut client = SentinelClient::build(
+                    seed_nodes(config),
+                    config.sentinel_master.clone(),
+                    Some(sentinel_node_info(config, &config.url)?),
+                    SentinelServerType::Master,
+                )?;
+                for _ in 0..size {
+                    let conn = with_timeout(connect_timeout, client.get_async_connection()).await?;
+                    conns.push(RedisConnection::Sentinel(conn));
+                }
+                Topology::Sentinel(tokio::sync::Mutex::new(client))
+            }
+            _ => return Err(Error::new(ErrorKind::CONFIG, "unknown redis mode")),
+        };
+        Ok(RedisPool {
+            conns: RwLock::new(conns),
+            next: AtomicUsize::new(0),
+            topology: topology,
+            connect_timeout: connect_timeout,
+            response_timeout: response_timeout,
+        })
+    }
+
+    fn get_indexed(&self) -> (usize, RedisConnection) {
+        let conns = self.conns.read().unwrap();
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % conns.len();
+        (index, conns[index].clone())
+    }
+
+    /*
+       执行 redis 请求，超时返回错误
+       let value: Option<String> = pool.run(|mut conn| async move { conn.get(key).await }).await?;
+    */
+    pub async fn run<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(RedisConnection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let (index, conn) = self.get_indexed();
+        match tokio::time::timeout(self.response_timeout, f(conn)).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(err)) => {
+                if need_failover(&err) {
+                    self.failover(index).await;
+                }
+                Err(err.into())
+            }
+            Err(_) => {
+                Err(Error::new(ErrorKind::REDIS, "redis response timeout").with_retry_after(1))
+            }
+        }
+    }
+
+    /*
+       sentinel 模式下重新查询 master 并替换失效连接，失败时保留原连接等待下次请求重试
+       single 和 cluster 模式由 redis 库自行重连
+    */
+    async fn failover(&self, index: usize) {
+        let sentinel = match &self.topology {
+            Topology::Sentinel(sentinel) => sentinel,
+            _ => return,
+        };
+        let mut client = sentinel.lock().await;
+        match with_timeout(self.connect_timeout, client.get_async_connection()).await {
+            Ok(conn) => self.conns.write().unwrap()[index] = RedisConnection::Sentinel(conn),
+            Err(err) => println!("redis sentinel reconnect error: {:?}", err),
+        }
+    }
+}
+
+fn need_failover(err: &RedisError) -> bool {
+    err.is_connection_dropped()
+        || err.is_connection_refusal()
+        || err.is_io_error()
+        || err.kind() == redis::ErrorKind::ReadOnly
+}
+
+async fn with_timeout<T, Fut>(timeout: Duration, fut: Fut) -> Result<T>
+where
+    Fut: Future<Output = RedisResult<T>>,
+{
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(res) => Ok(res?),
+        Err(_) => Err(Error::new(ErrorKind::REDIS, "redis connect timeout").with_retry_after(1)),
+    }
+}
+
+// 未配置 nodes 时使用 url
+fn seed_nodes(config: &config::Redis) -> Vec<String> {
+    match config.nodes.is_empty() {
+        true => vec![config.url.clone()],
+        false => config.nodes.clone(),
+    }
+}
+
+/*
+   节点连接信息，配置中的用户名、密码和 TLS 覆盖地址中的设置
+*/
+fn connection_info(config: &config::Redis, url: &str) -> Result<ConnectionInfo> {
+    let mut info = url.into_connection_info()?;
+    if !config.username.is_empty() {
+        info.redis.username = Some(config.username.clone());
+    }
+    if !config.auth_passwd.is_empty() {
+        info.redis.password = Some(config.auth_passwd.clone());
+    }
+    if config.tls {
+        if let ConnectionAddr::Tcp(host, port) = info.addr.clone() {
+            info.addr = ConnectionAddr::TcpTls {
+                host: host,
+                port: port,
+                insecure: config.tls_insecure,
+                tls_params: None,
+            };
+        }
+    }
+    Ok(info)
+}
+
+/*
+   哨兵返回的 master 连接信息，认证和 TLS 配置作用于 master，
+   哨兵节点自身的认证写在 nodes 地址中
+*/
+fn sentinel_node_info(config: &config::Redis, url: &str) -> Result<SentinelNodeConnectionInfo> {
+    let info = connection_info(config, url)?;
+    let tls_mode = match info.addr {
+        ConnectionAddr::TcpTls { insecure, .. } => Some(match insecure {
+            true => TlsMode::Insecure,
+            false => TlsMode::Secure,
+        }),
+        _ => None,
+    };
+    Ok(SentinelNodeConnectionInfo {
+        tls_mode: tls_mode,
+        redis_connection_info: Some(info.redis),
+    })
+}
+
+pub async fn init(config: &config::Redis) -> Result<()> {
+    let pool = RedisPool::connect(config).await?;
+    *mem::REDIS_POOL.write().unwrap() = Some(Arc::new(pool));
+    Ok(())
+}
+
+pub fn pool() -> Result<Arc<RedisPool>> {
+    match &*mem::REDIS_POOL.read().unwrap() {
+        Some(pool) => Ok(pool.clone()),
+        None => Err(Error::new(
+            ErrorKind::CONFIG,
+            "redis pool not initialized, check [redis] config",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn redis_config(mode: &str) -> config::Redis {
+        toml::from_str(&format!(
+            r#"
+            mode = "{}"
+            url = "redis://127.0.0.1:6379/2"
+            username = "stserver"
+            auth_passwd = "passwd"
+            tls = true
+            "#,
+            mode
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn node_connection_info() {
+        let config = redis_config("cluster");
+        let info = connection_info(&config, "redis://:other@10.0.0.1:7000").unwrap();
+        assert_eq!(info.redis.username.as_deref(), Some("stserver"));
+        assert_eq!(info.redis.password.as_deref(), Some("passwd"));
+        match info.addr {
+            ConnectionAddr::TcpTls {
+                host,
+                port,
+                insecure,
+                ..
+            } => {
+                assert_eq!((host.as_str(), port, insecure), ("10.0.0.1", 7000, false))
+            }
+            _ => panic!("tls not applied"),
+        }
+        assert_eq!(seed_nodes(&config), vec![config.url.clone()]);
+
+        let node =
+            sentinel_node_info(&redis_config("sentinel"), "redis://127.0.0.1:26379/2").unwrap();
+        assert!(matches!(node.tls_mode, Some(TlsMode::Secure)));
+        let redis = node.redis_connection_info.unwrap();
+        assert_eq!(redis.db, 2);
+        assert_eq!(redis.password.as_deref(), Some("passwd"));
+    }
+}
@@ -0,0 +1,245 @@
+/*
+   静态文件密钥仓库，启动时加载到内存
+   dir/keys.toml 格式，文件路径相对于 dir：
+
+   [[app]]
+   id = 1
+   name = "demo"
+   certs = "demo.p12"            # PKCS#12 证书库
+   certs_passwd = "ENC1:..."     # 证书库密码，主密钥加密
+   prev_certs = "demo.old.p12"   # 可选，轮换前的证书库，当前证书库没有有效证书时使用
+   prev_certs_passwd = "ENC1:..."
+
+   [[client]]
+   serialid = "..."
+   app_id = 1
+   client_type = 1
+   pubkey = "clients/abc.pub.pem"
+   prikey = "clients/abc.key.pem" # 内容为 PEM 或者主密钥加密后的 ENC1:...
+   key_version = 1                # 可选，默认 1，同一 serialid 可以配置多个版本
+   expires_at = 1700000000        # 可选，旧版本过期时间，unix 时间戳秒
+   status = 1                     # 可选，默认 0，1 表示已吊销
+   revoked_reason = "lost device"
+   revoked_at = 1700000000
+*/
+
+use std::{collections::HashMap, fs, path::Path};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::KeyRepository;
+use crate::{
+    error::{Error, ErrorKind, Result},
+    store::db::{App, AppClientKey},
+};
+
+const INDEX_FILE: &str = "keys.toml";
+
+#[derive(Deserialize)]
+struct KeysFile {
+    #[serde(default)]
+    app: Vec<AppEntry>,
+    #[serde(default)]
+    client: Vec<ClientEntry>,
+}
+
+#[derive(Deserialize)]
+struct AppEntry {
+    id: usize,
+    name: String,
+    description: Option<String>,
+    certs: Option<String>,
+    certs_passwd: Option<String>,
+    prev_certs: Option<String>,
+    prev_certs_passwd: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClientEntry {
+    serialid: String,
+    app_id: usize,
+    client_type: usize,
+    pubkey: Option<String>,
+    prikey: Option<String>,
+    #[serde(default = "default_key_version")]
+    key_version: u32,
+    expires_at: Option<i64>,
+    #[serde(default)]
+    status: u32,
+    revoked_reason: Option<String>,
+    revoked_at: Option<i64>,
+}
+
+fn default_key_version() -> u32 {
+    1
+}
+
+pub struct FileRepository {
+    apps: HashMap<usize, App>,
+    // 同一 serialid 的全部版本，新版本在前
+    client_keys: HashMap<String, Vec<AppClientKey>>,
+}
+
+impl FileRepository {
+    pub fn load(dir: &Path) -> Result<FileRepository> {
+        let index = fs::read(dir.join(INDEX_FILE))?;
+        let keys: KeysFile = toml::from_slice(&index)?;
+
+        let mut apps = HashMap::new();
+        for app in keys.app {
+            apps.insert(
+                app.id,
+                App {
+                    id: app.id,
+                    name: app.name,
+                    description: app.description,
+                    certs: read_file(dir, &app.certs)?,
+                    certs_passwd: app.certs_passwd,
+                    prev_certs: read_file(dir, &app.prev_certs)?,
+                    prev_certs_passwd: app.prev_certs_passwd,
+                },
+            );
+        }
+
+        let mut client_keys: HashMap<String, Vec<AppClientKey>> = HashMap::new();
+        for client in keys.client {
+            if !apps.contains_key(&client.app_id) {
+                return Err(Error::new(
+                    ErrorKind::CONFIG,
+                    &format!("client {} references unknown app", client.serialid),
+                ));
+            }
+            client_keys
+                .entry(client.serialid.clone())
+                .or_default()
+                .push(AppClientKey {
+                    app_id: client.app_id,
+                    client_type: client.client_type,
+                    serialid: Some(client.serialid),
+                    pubkey: read_pem(dir, &client.pubkey)?,
+                    prikey: read_pem(dir, &client.prikey)?,
+                    key_version: client.key_version,
+                    expires_at: client.expires_at,
+                    status: client.status,
+                    revoked_reason: client.revoked_reason,
+                    revoked_at: client.revoked_at,
+                });
+        }
+        for versions in client_keys.values_mut() {
+            versions.sort_by(|a, b| b.key_version.cmp(&a.key_version));
+        }
+
+        Ok(FileRepository {
+            apps: apps,
+            client_keys: client_keys,
+        })
+    }
+}
+
+fn read_file(dir: &Path, path: &Option<String>) -> Result<Option<Vec<u8>>> {
+    match path {
+        Some(path) => Ok(Some(fs::read(dir.join(path))?)),
+        None => Ok(None),
+    }
+}
+
+fn read_pem(dir: &Path, path: &Option<String>) -> Result<Option<String>> {
+    match path {
+        Some(path) => Ok(Some(fs::read_to_string(dir.join(path))?.trim().to_string())),
+        None => Ok(None),
+    }
+}
+
+#[async_trait]
+impl KeyRepository for FileRepository {
+    async fn client_key_versions(&self, serialid: &str) -> Result<Vec<AppClientKey>> {
+        Ok(self.client_keys.get(serialid).cloned().unwrap_or_default())
+    }
+
+    async fn app(&self, id: usize) -> Result<Option<App>> {
+        Ok(self.apps.get(&id).cloned())
+    }
+
+    async fn apps(&self) -> Result<Vec<App>> {
+        let mut apps: Vec<App> = self.apps.values().cloned().collect();
+        apps.sort_by_key(|app| app.id);
+        Ok(apps)
+    }
+
+    async fn client_keys(&self, app_id: Option<usize>) -> Result<Vec<AppClientKey>> {
+        let mut keys: Vec<AppClientKey> = self
+            .client_keys
+            .values()
+            .flatten()
+            .filter(|key| app_id.map_or(true, |app_id| key.app_id == app_id))
+            .cloned()
+            .collect();
+        keys.sort_by(|a, b| {
+            (a.app_id, &a.serialid, a.key_version).cmp(&(b.app_id, &b.serialid, b.key_version))
+        });
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn load_dir() {
+        let dir = std::env::temp_dir().join(format!("stserver-keys-{}", std::process::id()));
+        fs::create_dir_all(dir.join("clients")).unwrap();
+        fs::write(dir.join("demo.p12"), &[1u8, 2, 3]).unwrap();
+        fs::write(dir.join("clients/abc.key.pem"), "PRIVATE KEY").unwrap();
+        fs::write(
+            dir.join(INDEX_FILE),
+            r#"
+            [[app]]
+            id = 1
+            name = "demo"
+            certs = "demo.p12"
+
+            [[client]]
+            serialid = "abc"
+            app_id = 1
+            client_type = 2
+            prikey = "clients/abc.key.pem"
+
+            [[client]]
+            serialid = "abc"
+            app_id = 1
+            client_type = 2
+            key_version = 2
+
+            [[client]]
+            serialid = "def"
+            app_id = 1
+            client_type = 2
+            status = 1
+            revoked_reason = "lost device"
+            revoked_at = 100
+            "#,
+        )
+        .unwrap();
+
+        let repository = FileRepository::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let app = repository.app(1).await.unwrap().unwrap();
+        assert_eq!(app.certs, Some(vec![1, 2, 3]));
+        let key = repository.client_key("abc").await.unwrap().unwrap();
+        assert_eq!((key.client_type, key.key_version), (2, 2));
+        let versions = repository.client_key_versions("abc").await.unwrap();
+        assert_eq!(versions[1].key_version, 1);
+        assert_eq!(versions[1].pubkey, None);
+        assert_eq!(versions[1].prikey.as_deref(), Some("PRIVATE KEY"));
+        assert!(!key.is_revoked());
+        assert_eq!(repository.client_key("none").await.unwrap(), None);
+
+        let revoked = repository.client_key("def").await.unwrap().unwrap();
+        assert!(revoked.is_revoked());
+        assert_eq!(revoked.revoked_reason.as_deref(), Some("lost device"));
+        assert_eq!(repository.revoked_serialids().await.unwrap(), vec!["def"]);
+    }
+}
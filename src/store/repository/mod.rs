@@ -0,0 +1,160 @@
+/*
+   项目和客户端密钥仓库
+   1 mysql 默认，使用 [mysql] 连接池
+   2 postgres
+   3 sqlite 单节点部署和 CI
+   4 file 静态文件目录，keys.toml 描述项目和客户端，密钥和证书放在 PEM/PKCS#12 文件中
+   通过 [repository] backend 配置选择，握手时经 keycache 读取
+*/
+
+mod file;
+mod mysql;
+mod postgres;
+mod sqlite;
+
+use std::{path::Path, sync::Arc};
+
+use async_trait::async_trait;
+
+pub use self::{
+    file::FileRepository, mysql::MysqlRepository, postgres::PostgresRepository,
+    sqlite::SqliteRepository,
+};
+use super::{
+    db::{App, AppClientKey},
+    mem,
+};
+use crate::{
+    config,
+    error::{Error, ErrorKind, Result},
+};
+
+#[async_trait]
+pub trait KeyRepository: Send + Sync {
+    // 客户端全部密钥版本，新版本在前
+    async fn client_key_versions(&self, serialid: &str) -> Result<Vec<AppClientKey>>;
+    async fn app(&self, id: usize) -> Result<Option<App>>;
+
+    // 最新版本
+    async fn client_key(&self, serialid: &str) -> Result<Option<AppClientKey>> {
+        Ok(self.client_key_versions(serialid).await?.into_iter().next())
+    }
+
+    // 以下供 keys 管理命令使用，只读仓库不实现写接口
+    async fn apps(&self) -> Result<Vec<App>>;
+    async fn client_keys(&self, app_id: Option<usize>) -> Result<Vec<AppClientKey>>;
+
+    async fn create_app(&self, _name: &str, _description: Option<&str>) -> Result<usize> {
+        Err(read_only())
+    }
+
+    async fn set_keystore(&self, _id: usize, _certs: &[u8], _certs_passwd: &str) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn retire_keystore(&self, _id: usize) -> Result<()> {
+        Err(read_only())
+    }
+
+    // keys seal 加密历史数据时原地更新证书库密码，不移动证书库版本
+    async fn update_keystore_passwd(
+        &self,
+        _id: usize,
+        _certs_passwd: Option<&str>,
+        _prev_certs_passwd: Option<&str>,
+    ) -> Result<()> {
+        Err(read_only())
+    }
+
+    async fn insert_client_key(&self, _key: &AppClientKey) -> Result<()> {
+        Err(read_only())
+    }
+
+    // keys seal 加密历史数据时原地更新私钥
+    async fn update_client_prikey(
+        &self,
+        _serialid: &str,
+        _key_version: u32,
+        _prikey: &str,
+    ) -> Result<()> {
+        Err(read_only())
+    }
+
+    // 轮换时设置已有版本的过期时间
+    async fn expire_client_key(&self, _serialid: &str, _expires_at: i64) -> Result<()> {
+        Err(read_only())
+    }
+
+    // 吊销全部版本，保留记录和吊销原因，返回是否存在
+    async fn revoke_client_key(
+        &self,
+        _serialid: &str,
+        _reason: &str,
+        _revoked_at: i64,
+    ) -> Result<bool> {
+        Err(read_only())
+    }
+
+    // 已吊销的客户端，启动时加载到吊销名单
+    async fn revoked_serialids(&self) -> Result<Vec<String>> {
+        let mut res: Vec<String> = self
+            .client_keys(None)
+            .await?
+            .into_iter()
+            .filter(|key| key.is_revoked())
+            .filter_map(|key| key.serialid)
+            .collect();
+        res.dedup();
+        Ok(res)
+    }
+
+    // 删除全部版本，返回是否存在
+    async fn delete_client_key(&self, _serialid: &str) -> Result<bool> {
+        Err(read_only())
+    }
+}
+
+fn read_only() -> Error {
+    Error::new(ErrorKind::CONFIG, "key repository is read-only")
+}
+
+/*
+   根据配置初始化密钥仓库，mysql 仓库需先初始化 mysql_pool
+*/
+pub async fn init(config: &config::Config) -> Result<()> {
+    let repository: Arc<dyn KeyRepository> = match &config.repository {
+        None => Arc::new(MysqlRepository::new()),
+        Some(repository) => match repository.backend.as_str() {
+            "mysql" => Arc::new(MysqlRepository::new()),
+            "postgres" => Arc::new(PostgresRepository::connect(repository).await?),
+            "sqlite" => Arc::new(SqliteRepository::open(Path::new(required(
+                &repository.path,
+                "sqlite repository requires path",
+            )?))?),
+            "file" => Arc::new(FileRepository::load(Path::new(required(
+                &repository.dir,
+                "file repository requires dir",
+            )?))?),
+            _ => return Err(Error::new(ErrorKind::CONFIG, "unknown repository backend")),
+        },
+    };
+    *mem::KEY_REPOSITORY.write().unwrap() = Some(repository);
+    Ok(())
+}
+
+fn required<'a>(value: &'a Option<String>, msg: &str) -> Result<&'a str> {
+    match value {
+        Some(value) => Ok(value.as_str()),
+        None => Err(Error::new(ErrorKind::CONFIG, msg)),
+    }
+}
+
+pub fn repository() -> Result<Arc<dyn KeyRepository>> {
+    match &*mem::KEY_REPOSITORY.read().unwrap() {
+        Some(repository) => Ok(repository.clone()),
+        None => Err(Error::new(
+            ErrorKind::CONFIG,
+            "key repository not initialized",
+        )),
+    }
+}
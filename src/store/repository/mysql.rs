@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+
+use super::KeyRepository;
+use crate::{
+    error::Result,
+    store::db::{App, AppClientKey},
+};
+
+pub struct MysqlRepository;
+
+impl MysqlRepository {
+    pub fn new() -> MysqlRepository {
+        MysqlRepository
+    }
+}
+
+#[async_trait]
+impl KeyRepository for MysqlRepository {
+    async fn client_key_versions(&self, serialid: &str) -> Result<Vec<AppClientKey>> {
+        AppClientKey::versions(serialid).await
+    }
+
+    async fn app(&self, id: usize) -> Result<Option<App>> {
+        App::get(id).await
+    }
+
+    async fn apps(&self) -> Result<Vec<App>> {
+        App::list().await
+    }
+
+    async fn client_keys(&self, app_id: Option<usize>) -> Result<Vec<AppClientKey>> {
+        AppClientKey::list(app_id).await
+    }
+
+    async fn create_app(&self, name: &str, description: Option<&str>) -> Result<usize> {
+        App::insert(name, description).await
+    }
+
+    async fn set_keystore(&self, id: usize, certs: &[u8], certs_passwd: &str) -> Result<()> {
+        App::set_keystore(id, certs, certs_passwd).await
+    }
+
+    async fn retire_keystore(&self, id: usize) -> Result<()> {
+        App::retire_keystore(id).await
+    }
+
+    async fn update_keystore_passwd(
+        &self,
+        id: usize,
+        certs_passwd: Option<&str>,
+        prev_certs_passwd: Option<&str>,
+    ) -> Result<()> {
+        App::update_passwd(id, certs_passwd, prev_certs_passwd).await
+    }
+
+    async fn insert_client_key(&self, key: &AppClientKey) -> Result<()> {
+        key.insert().await
+    }
+
+    async fn update_client_prikey(
+        &self,
+        serialid: &str,
+        key_version: u32,
+        prikey: &str,
+    ) -> Result<()> {
+        AppClientKey::update_prikey(serialid, key_version, prikey).await
+    }
+
+    async fn expire_client_key(&self, serialid: &str, expires_at: i64) -> Result<()> {
+        AppClientKey::expire(serialid, expires_at).await
+    }
+
+    async fn revoke_client_key(
+        &self,
+        serialid: &str,
+        reason: &str,
+        revoked_at: i64,
+    ) -> Result<bool> {
+        AppClientKey::revoke(serialid, reason, revoked_at).await
+    }
+
+    async fn revoked_serialids(&self) -> Result<Vec<String>> {
+        AppClientKey::revoked_serialids().await
+    }
+
+    async fn delete_client_key(&self, serialid: &str) -> Result<bool> {
+        AppClientKey::delete(serialid).await
+    }
+}
@@ -0,0 +1,321 @@
+/*
+   postgres 密钥仓库
+   建立 pool_size 个连接轮询使用，连接断开后下一次请求时重连
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio_postgres::{Client, NoTls, Row};
+
+use super::KeyRepository;
+use crate::{
+    config,
+    error::{Error, ErrorKind, Result},
+    store::db::{App, AppClientKey, KEY_STATUS_REVOKED},
+};
+
+pub struct PostgresRepository {
+    url: String,
+    clients: Vec<Mutex<Option<Arc<Client>>>>,
+    next: AtomicUsize,
+    connect_timeout: Duration,
+}
+
+impl PostgresRepository {
+    pub async fn connect(config: &config::Repository) -> Result<PostgresRepository> {
+        let url = match &config.url {
+            Some(url) => url.clone(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::CONFIG,
+                    "postgres repository requires url",
+                ))
+            }
+        };
+        let repository = PostgresRepository {
+            url: url,
+            clients: (0..config.pool_size.max(1))
+                .map(|_| Mutex::new(None))
+                .collect(),
+            next: AtomicUsize::new(0),
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+        };
+        // 启动时检查连接配置
+        repository.client().await?;
+        Ok(repository)
+    }
+
+    async fn client(&self) -> Result<Arc<Client>> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+        if let Some(client) = &*self.clients[index].lock().unwrap() {
+            if !client.is_closed() {
+                return Ok(client.clone());
+            }
+        }
+        let connect = tokio_postgres::connect(&self.url, NoTls);
+        let (client, connection) = match tokio::time::timeout(self.connect_timeout, connect).await {
+            Ok(res) => res?,
+            Err(_) => {
+                return Err(
+                    Error::new(ErrorKind::POSTGRES, "postgres connect timeout").with_retry_after(1)
+                )
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                println!("postgres connection error: {:?}", err);
+            }
+        });
+        let client = Arc::new(client);
+        *self.clients[index].lock().unwrap() = Some(client.clone());
+        Ok(client)
+    }
+}
+
+const CLIENT_KEY_COLUMNS: &str = "select app_id::bigint, client_type::bigint, serialid, pubkey, \
+                                  prikey, key_version::bigint, expires_at, status::bigint, \
+                                  revoked_reason, revoked_at from app_client_key";
+const APP_COLUMNS: &str = "select id::bigint, name, description, certs, certs_passwd, \
+                           prev_certs, prev_certs_passwd from app";
+
+fn client_key_from_row(row: &Row) -> AppClientKey {
+    AppClientKey {
+        app_id: row.get::<_, i64>("app_id") as usize,
+        client_type: row.get::<_, i64>("client_type") as usize,
+        serialid: row.get("serialid"),
+        pubkey: row.get("pubkey"),
+        prikey: row.get("prikey"),
+        key_version: row.get::<_, i64>("key_version") as u32,
+        expires_at: row.get("expires_at"),
+        status: row.get::<_, i64>("status") as u32,
+        revoked_reason: row.get("revoked_reason"),
+        revoked_at: row.get("revoked_at"),
+    }
+}
+
+fn app_from_row(row: &Row) -> App {
+    App {
+        id: row.get::<_, i64>("id") as usize,
+        name: row.get("name"),
+        description: row.get("description"),
+        certs: row.get("certs"),
+        certs_passwd: row.get("certs_passwd"),
+        prev_certs: row.get("prev_certs"),
+        prev_certs_passwd: row.get("prev_certs_passwd"),
+    }
+}
+
+#[async_trait]
+impl KeyRepository for PostgresRepository {
+    async fn client_key_versions(&self, serialid: &str) -> Result<Vec<AppClientKey>> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                format!(
+                    "{} where serialid = $1 order by key_version desc",
+                    CLIENT_KEY_COLUMNS
+                )
+                .as_str(),
+                &[&serialid],
+            )
+            .await?;
+        Ok(rows.iter().map(client_key_from_row).collect())
+    }
+
+    async fn app(&self, id: usize) -> Result<Option<App>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                format!("{} where id = $1", APP_COLUMNS).as_str(),
+                &[&(id as i64)],
+            )
+            .await?;
+        Ok(row.as_ref().map(app_from_row))
+    }
+
+    async fn apps(&self) -> Result<Vec<App>> {
+        let rows = self
+            .client()
+            .await?
+            .query(format!("{} order by id", APP_COLUMNS).as_str(), &[])
+            .await?;
+        Ok(rows.iter().map(app_from_row).collect())
+    }
+
+    async fn client_keys(&self, app_id: Option<usize>) -> Result<Vec<AppClientKey>> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                format!(
+                    "{} where $1::bigint is null or app_id = $1 \
+                     order by app_id, serialid, key_version",
+                    CLIENT_KEY_COLUMNS
+                )
+                .as_str(),
+                &[&app_id.map(|app_id| app_id as i64)],
+            )
+            .await?;
+        Ok(rows.iter().map(client_key_from_row).collect())
+    }
+
+    async fn create_app(&self, name: &str, description: Option<&str>) -> Result<usize> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "insert into app(name, description) values ($1, $2) returning id::bigint",
+                &[&name, &description],
+            )
+            .await?;
+        Ok(row.get::<_, i64>(0) as usize)
+    }
+
+    async fn set_keystore(&self, id: usize, certs: &[u8], certs_passwd: &str) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update app set prev_certs = certs, prev_certs_passwd = certs_passwd, \
+                 certs = $2, certs_passwd = $3 where id = $1",
+                &[&(id as i64), &certs, &certs_passwd],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn retire_keystore(&self, id: usize) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update app set prev_certs = null, prev_certs_passwd = null where id = $1",
+                &[&(id as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn update_keystore_passwd(
+        &self,
+        id: usize,
+        certs_passwd: Option<&str>,
+        prev_certs_passwd: Option<&str>,
+    ) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update app set certs_passwd = $2, prev_certs_passwd = $3 where id = $1",
+                &[&(id as i64), &certs_passwd, &prev_certs_passwd],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_client_key(&self, key: &AppClientKey) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "insert into app_client_key(app_id, client_type, serialid, pubkey, prikey, \
+                 key_version, expires_at, status, revoked_reason, revoked_at) \
+                 values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                &[
+                    &(key.app_id as i64),
+                    &(key.client_type as i64),
+                    &key.serialid,
+                    &key.pubkey,
+                    &key.prikey,
+                    &(key.key_version as i64),
+                    &key.expires_at,
+                    &(key.status as i64),
+                    &key.revoked_reason,
+                    &key.revoked_at,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn update_client_prikey(
+        &self,
+        serialid: &str,
+        key_version: u32,
+        prikey: &str,
+    ) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update app_client_key set prikey = $3 where serialid = $1 and key_version = $2",
+                &[&serialid, &(key_version as i64), &prikey],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn expire_client_key(&self, serialid: &str, expires_at: i64) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update app_client_key set expires_at = $2 where serialid = $1 \
+                 and (expires_at is null or expires_at > $2)",
+                &[&serialid, &expires_at],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_client_key(
+        &self,
+        serialid: &str,
+        reason: &str,
+        revoked_at: i64,
+    ) -> Result<bool> {
+        let count = self
+            .client()
+            .await?
+            .execute(
+                "update app_client_key set status = $2, revoked_reason = $3, revoked_at = $4 \
+                 where serialid = $1",
+                &[
+                    &serialid,
+                    &(KEY_STATUS_REVOKED as i64),
+                    &reason,
+                    &revoked_at,
+                ],
+            )
+            .await?;
+        Ok(count > 0)
+    }
+
+    async fn revoked_serialids(&self) -> Result<Vec<String>> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "select distinct serialid from app_client_key \
+                 where status = $1 and serialid is not null",
+                &[&(KEY_STATUS_REVOKED as i64)],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn delete_client_key(&self, serialid: &str) -> Result<bool> {
+        let count = self
+            .client()
+            .await?
+            .execute(
+                "delete from app_client_key where serialid = $1",
+                &[&serialid],
+            )
+            .await?;
+        Ok(count > 0)
+    }
+}
@@ -0,0 +1,445 @@
+/*
+   sqlite 密钥仓库
+   rusqlite 是同步接口，查询放到 tokio 阻塞线程池中执行
+*/
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::KeyRepository;
+use crate::{
+    error::{Error, ErrorKind, Result},
+    store::db::{App, AppClientKey, KEY_STATUS_REVOKED},
+};
+
+pub struct SqliteRepository {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteRepository {
+    pub fn open(path: &Path) -> Result<SqliteRepository> {
+        Ok(SqliteRepository::with_connection(Connection::open(path)?))
+    }
+
+    pub fn with_connection(conn: Connection) -> SqliteRepository {
+        SqliteRepository {
+            conn: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        match tokio::task::spawn_blocking(move || f(&conn.lock().unwrap())).await {
+            Ok(res) => Ok(res?),
+            Err(err) => Err(Error::new(ErrorKind::SQLITE, &err.to_string())),
+        }
+    }
+}
+
+const CLIENT_KEY_COLUMNS: &str = "select app_id, client_type, serialid, pubkey, prikey, \
+                                  key_version, expires_at, status, revoked_reason, revoked_at \
+                                  from app_client_key";
+const APP_COLUMNS: &str =
+    "select id, name, description, certs, certs_passwd, prev_certs, prev_certs_passwd from app";
+
+fn client_key_from_row(row: &Row) -> rusqlite::Result<AppClientKey> {
+    Ok(AppClientKey {
+        app_id: row.get::<_, i64>("app_id")? as usize,
+        client_type: row.get::<_, i64>("client_type")? as usize,
+        serialid: row.get("serialid")?,
+        pubkey: row.get("pubkey")?,
+        prikey: row.get("prikey")?,
+        key_version: row.get::<_, i64>("key_version")? as u32,
+        expires_at: row.get("expires_at")?,
+        status: row.get::<_, i64>("status")? as u32,
+        revoked_reason: row.get("revoked_reason")?,
+        revoked_at: row.get("revoked_at")?,
+    })
+}
+
+fn app_from_row(row: &Row) -> rusqlite::Result<App> {
+    Ok(App {
+        id: row.get::<_, i64>("id")? as usize,
+        name: row.get("name")?,
+        description: row.get("description")?,
+        certs: row.get("certs")?,
+        certs_passwd: row.get("certs_passwd")?,
+        prev_certs: row.get("prev_certs")?,
+        prev_certs_passwd: row.get("prev_certs_passwd")?,
+    })
+}
+
+#[async_trait]
+impl KeyRepository for SqliteRepository {
+    async fn client_key_versions(&self, serialid: &str) -> Result<Vec<AppClientKey>> {
+        let serialid = serialid.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "{} where serialid = ?1 order by key_version desc",
+                CLIENT_KEY_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![serialid], client_key_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn app(&self, id: usize) -> Result<Option<App>> {
+        self.run(move |conn| {
+            conn.query_row(
+                &format!("{} where id = ?1", APP_COLUMNS),
+                params![id as i64],
+                app_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn apps(&self) -> Result<Vec<App>> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare(&format!("{} order by id", APP_COLUMNS))?;
+            let rows = stmt.query_map([], app_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn client_keys(&self, app_id: Option<usize>) -> Result<Vec<AppClientKey>> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "{} where ?1 is null or app_id = ?1 order by app_id, serialid, key_version",
+                CLIENT_KEY_COLUMNS
+            ))?;
+            let rows = stmt.query_map(
+                params![app_id.map(|app_id| app_id as i64)],
+                client_key_from_row,
+            )?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn create_app(&self, name: &str, description: Option<&str>) -> Result<usize> {
+        let name = name.to_string();
+        let description = description.map(|description| description.to_string());
+        self.run(move |conn| {
+            conn.execute(
+                "insert into app(name, description) values (?1, ?2)",
+                params![name, description],
+            )?;
+            Ok(conn.last_insert_rowid() as usize)
+        })
+        .await
+    }
+
+    async fn set_keystore(&self, id: usize, certs: &[u8], certs_passwd: &str) -> Result<()> {
+        let certs = certs.to_vec();
+        let certs_passwd = certs_passwd.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "update app set prev_certs = certs, prev_certs_passwd = certs_passwd, \
+                 certs = ?2, certs_passwd = ?3 where id = ?1",
+                params![id as i64, certs, certs_passwd],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn retire_keystore(&self, id: usize) -> Result<()> {
+        self.run(move |conn| {
+            conn.execute(
+                "update app set prev_certs = null, prev_certs_passwd = null where id = ?1",
+                params![id as i64],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_keystore_passwd(
+        &self,
+        id: usize,
+        certs_passwd: Option<&str>,
+        prev_certs_passwd: Option<&str>,
+    ) -> Result<()> {
+        let certs_passwd = certs_passwd.map(str::to_string);
+        let prev_certs_passwd = prev_certs_passwd.map(str::to_string);
+        self.run(move |conn| {
+            conn.execute(
+                "update app set certs_passwd = ?2, prev_certs_passwd = ?3 where id = ?1",
+                params![id as i64, certs_passwd, prev_certs_passwd],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn insert_client_key(&self, key: &AppClientKey) -> Result<()> {
+        let key = key.clone();
+        self.run(move |conn| {
+            conn.execute(
+                "insert into app_client_key(app_id, client_type, serialid, pubkey, prikey, \
+                 key_version, expires_at, status, revoked_reason, revoked_at) \
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    key.app_id as i64,
+                    key.client_type as i64,
+                    key.serialid,
+                    key.pubkey,
+                    key.prikey,
+                    key.key_version as i64,
+                    key.expires_at,
+                    key.status as i64,
+                    key.revoked_reason,
+                    key.revoked_at
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_client_prikey(
+        &self,
+        serialid: &str,
+        key_version: u32,
+        prikey: &str,
+    ) -> Result<()> {
+        let serialid = serialid.to_string();
+        let prikey = prikey.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "update app_client_key set prikey = ?3 where serialid = ?1 and key_version = ?2",
+                params![serialid, key_version as i64, prikey],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn expire_client_key(&self, serialid: &str, expires_at: i64) -> Result<()> {
+        let serialid = serialid.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "update app_client_key set expires_at = ?2 where serialid = ?1 \
+                 and (expires_at is null or expires_at > ?2)",
+                params![serialid, expires_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_client_key(
+        &self,
+        serialid: &str,
+        reason: &str,
+        revoked_at: i64,
+    ) -> Result<bool> {
+        let serialid = serialid.to_string();
+        let reason = reason.to_string();
+        self.run(move |conn| {
+            Ok(conn.execute(
+                "update app_client_key set status = ?2, revoked_reason = ?3, revoked_at = ?4 \
+                 where serialid = ?1",
+                params![serialid, KEY_STATUS_REVOKED as i64, reason, revoked_at],
+            )? > 0)
+        })
+        .await
+    }
+
+    async fn revoked_serialids(&self) -> Result<Vec<String>> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare(
+                "select distinct serialid from app_client_key \
+                 where status = ?1 and serialid is not null",
+            )?;
+            let rows = stmt.query_map(params![KEY_STATUS_REVOKED as i64], |row| row.get(0))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn delete_client_key(&self, serialid: &str) -> Result<bool> {
+        let serialid = serialid.to_string();
+        self.run(move |conn| {
+            Ok(conn.execute(
+                "delete from app_client_key where serialid = ?1",
+                params![serialid],
+            )? > 0)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::migrate;
+
+    // 使用实际的迁移脚本建表，保证与线上结构一致
+    fn repository() -> SqliteRepository {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in migrate::SQLITE_MIGRATIONS {
+            conn.execute_batch(migration.sql).unwrap();
+        }
+        conn.execute_batch(
+            "insert into app(id, name, certs) values (1, 'demo', x'0102');
+             insert into app_client_key(app_id, client_type, serialid, pubkey, prikey)
+                 values (1, 2, 'abc', 'pub', 'pri');",
+        )
+        .unwrap();
+        SqliteRepository::with_connection(conn)
+    }
+
+    #[tokio::test]
+    async fn app_lookup() {
+        let repository = repository();
+        let app = repository.app(1).await.unwrap().unwrap();
+        assert_eq!(app.name, "demo");
+        assert_eq!(app.certs, Some(vec![1, 2]));
+        assert_eq!(repository.app(2).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn client_key_lookup() {
+        let repository = repository();
+        let key = repository.client_key("abc").await.unwrap().unwrap();
+        assert_eq!((key.app_id, key.client_type), (1, 2));
+        assert_eq!((key.key_version, key.status), (1, 0));
+        assert_eq!(key.prikey.as_deref(), Some("pri"));
+        assert_eq!(repository.client_key("none").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn keystore_rotation() {
+        let repository = repository();
+        let id = repository.create_app("other", Some("desc")).await.unwrap();
+        repository
+            .set_keystore(id, &[3, 4], "ENC1:00")
+            .await
+            .unwrap();
+        repository
+            .set_keystore(id, &[5, 6], "ENC1:01")
+            .await
+            .unwrap();
+        let app = repository.app(id).await.unwrap().unwrap();
+        assert_eq!(app.certs, Some(vec![5, 6]));
+        assert_eq!(app.prev_certs, Some(vec![3, 4]));
+        assert_eq!(app.prev_certs_passwd.as_deref(), Some("ENC1:00"));
+        repository.retire_keystore(id).await.unwrap();
+        assert_eq!(repository.app(id).await.unwrap().unwrap().prev_certs, None);
+        assert_eq!(repository.apps().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn client_key_listing() {
+        let repository = repository();
+        let mut other = repository.client_key("abc").await.unwrap().unwrap();
+        other.app_id = 2;
+        other.serialid = Some("def".to_string());
+        repository.insert_client_key(&other).await.unwrap();
+        assert_eq!(repository.client_keys(None).await.unwrap().len(), 2);
+        assert_eq!(
+            repository.client_keys(Some(2)).await.unwrap(),
+            vec![other.clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn client_key_rotation() {
+        let repository = repository();
+        let mut rotated = repository.client_key("abc").await.unwrap().unwrap();
+        rotated.key_version = 2;
+        repository.expire_client_key("abc", 100).await.unwrap();
+        repository.insert_client_key(&rotated).await.unwrap();
+        let versions = repository.client_key_versions("abc").await.unwrap();
+        assert_eq!(
+            versions
+                .iter()
+                .map(|key| (key.key_version, key.expires_at))
+                .collect::<Vec<_>>(),
+            vec![(2, None), (1, Some(100))]
+        );
+    }
+
+    #[tokio::test]
+    async fn seal_in_place() {
+        let repository = repository();
+        repository
+            .update_keystore_passwd(1, Some("ENC1:02"), None)
+            .await
+            .unwrap();
+        let app = repository.app(1).await.unwrap().unwrap();
+        assert_eq!(app.certs, Some(vec![1, 2]));
+        assert_eq!(app.certs_passwd.as_deref(), Some("ENC1:02"));
+
+        repository
+            .update_client_prikey("abc", 1, "ENC1:03")
+            .await
+            .unwrap();
+        let key = repository.client_key("abc").await.unwrap().unwrap();
+        assert_eq!(key.prikey.as_deref(), Some("ENC1:03"));
+        assert_eq!(key.pubkey.as_deref(), Some("pub"));
+    }
+
+    #[tokio::test]
+    async fn revoke() {
+        let repository = repository();
+        let mut rotated = repository.client_key("abc").await.unwrap().unwrap();
+        rotated.key_version = 2;
+        repository.insert_client_key(&rotated).await.unwrap();
+
+        assert!(repository.revoked_serialids().await.unwrap().is_empty());
+        assert!(repository
+            .revoke_client_key("abc", "lost device", 200)
+            .await
+            .unwrap());
+        assert!(!repository
+            .revoke_client_key("none", "lost device", 200)
+            .await
+            .unwrap());
+        let versions = repository.client_key_versions("abc").await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert!(versions.iter().all(|key| key.is_revoked()
+            && key.revoked_reason.as_deref() == Some("lost device")
+            && key.revoked_at == Some(200)));
+        assert_eq!(repository.revoked_serialids().await.unwrap(), vec!["abc"]);
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let repository = repository();
+        assert!(repository.delete_client_key("abc").await.unwrap());
+        assert!(!repository.delete_client_key("abc").await.unwrap());
+        assert_eq!(repository.client_key("abc").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn recreate_after_delete() {
+        let repository = repository();
+        let key = repository.client_key("abc").await.unwrap().unwrap();
+        assert!(repository
+            .revoke_client_key("abc", "lost device", 200)
+            .await
+            .unwrap());
+        assert!(repository.delete_client_key("abc").await.unwrap());
+        assert!(repository.revoked_serialids().await.unwrap().is_empty());
+
+        repository.insert_client_key(&key).await.unwrap();
+        let recreated = repository.client_key("abc").await.unwrap().unwrap();
+        assert!(!recreated.is_revoked());
+        assert!(repository.revoked_serialids().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+/*
+   会话存储
+   1 memory 进程内存储，适用于单节点部署和不依赖 redis 的测试
+   2 redis 多节点共享存储
+   通过 [session] store 配置选择，默认 redis
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use super::{
+    cache::{RedisSessionStore, Session},
+    mem, redis_pool,
+};
+use crate::{
+    config::Config,
+    error::{Error, ErrorKind, Result},
+};
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn get(&self, token: &[u8]) -> Result<Option<Session>>;
+    // ttl 为 None 时永不过期
+    async fn set(&self, session: &Session, ttl: Option<Duration>) -> Result<()>;
+    // 只更新仍然存在的会话的最后活跃时间和过期时间，返回会话是否存在
+    async fn touch(&self, session: &Session, ttl: Option<Duration>) -> Result<bool>;
+    async fn delete(&self, token: &[u8]) -> Result<()>;
+}
+
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<Vec<u8>, (Session, Option<Instant>)>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore {
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 清理已过期的会话
+    pub fn purge(&self) {
+        let now = Instant::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, (_, deadline)| deadline.map_or(true, |deadline| deadline > now));
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn get(&self, token: &[u8]) -> Result<Option<Session>> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(match sessions.get(token) {
+            Some((_, Some(deadline))) if *deadline <= Instant::now() => None,
+            Some((session, _)) => Some(session.clone()),
+            None => None,
+        })
+    }
+
+    async fn set(&self, session: &Session, ttl: Option<Duration>) -> Result<()> {
+        let deadline = ttl.map(|ttl| Instant::now() + ttl);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.token.clone(), (session.clone(), deadline));
+        Ok(())
+    }
+
+    async fn touch(&self, session: &Session, ttl: Option<Duration>) -> Result<bool> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(&session.token) {
+            Some((current, deadline)) if deadline.map_or(true, |deadline| deadline > now) => {
+                current.last_active = session.last_active;
+                *deadline = ttl.map(|ttl| now + ttl);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&self, token: &[u8]) -> Result<()> {
+        self.sessions.lock().unwrap().remove(token);
+        Ok(())
+    }
+}
+
+/*
+   根据配置初始化会话存储，需在 tokio 运行时中调用
+   redis 存储需先初始化 redis_pool
+*/
+pub fn init(config: &Config) -> Result<()> {
+    let backend = match &config.session {
+        Some(session) => session.store.clone(),
+        None => "redis".to_string(),
+    };
+    let store: Arc<dyn SessionStore> = match backend.as_str() {
+        "memory" => {
+            let store = Arc::new(MemorySessionStore::new());
+            let purge_store = store.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(Duration::from_secs(60));
+                loop {
+                    ticker.tick().await;
+                    purge_store.purge();
+                }
+            });
+            store
+        }
+        "redis" => Arc::new(RedisSessionStore::new(redis_pool::pool()?)),
+        _ => return Err(Error::new(ErrorKind::CONFIG, "unknown session store")),
+    };
+    *mem::SESSION_STORE.write().unwrap() = Some(store);
+    Ok(())
+}
+
+pub fn store() -> Result<Arc<dyn SessionStore>> {
+    match &*mem::SESSION_STORE.read().unwrap() {
+        Some(store) => Ok(store.clone()),
+        None => Err(Error::new(
+            ErrorKind::CONFIG,
+            "session store not initialized",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_store() {
+        let store = MemorySessionStore::new();
+        let session = Session::init(
+            &vec![1; 40],
+            1,
+            "abc",
+            &vec![],
+            &vec![],
+            &vec![],
+            &vec![],
+            &vec![],
+        );
+        store.set(&session, None).await.unwrap();
+        assert_eq!(
+            store.get(&session.token).await.unwrap(),
+            Some(session.clone())
+        );
+
+        let mut active = session.clone();
+        active.last_active += 10;
+        assert!(store.touch(&active, None).await.unwrap());
+        assert_eq!(
+            store.get(&session.token).await.unwrap(),
+            Some(active.clone())
+        );
+
+        // 已删除的会话不会被重新写入
+        store.delete(&session.token).await.unwrap();
+        assert_eq!(store.get(&session.token).await.unwrap(), None);
+        assert!(!store.touch(&active, None).await.unwrap());
+        assert_eq!(store.get(&session.token).await.unwrap(), None);
+
+        store
+            .set(&session, Some(Duration::from_secs(0)))
+            .await
+            .unwrap();
+        assert_eq!(store.get(&session.token).await.unwrap(), None);
+        store.purge();
+        assert!(store.sessions.lock().unwrap().is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::error::{self, Error, ErrorKind};
 use chrono::{Datelike, Local, Timelike};
+use openssl::asn1::Asn1Time;
+use openssl::nid::Nid;
 use openssl::pkcs12::Pkcs12;
 use openssl::rsa::{Padding, Rsa};
 use openssl::symm::{Cipher, Crypter, Mode};
@@ -89,23 +91,75 @@ pub fn rsa_privatekey_decrypt(data: &Vec<u8>, privatekey: &Vec<u8>) -> Result<Ve
     Ok(encrypted_data)
 }
 
+/*
+   从证书链中随机选取一张当前有效的证书，过期和未生效的证书不下发
+*/
 pub fn get_random_x509(buff: &[u8], pass: &str) -> error::Result<Vec<u8>> {
     let pkcs12 = Pkcs12::from_der(buff)?;
-    let parsepkcs12 = pkcs12.parse(pass)?;
-    match parsepkcs12.chain {
+    let parsepkcs12 = pkcs12.parse2(pass)?;
+    match parsepkcs12.ca {
         Some(x509_stack) => {
+            let now = Asn1Time::days_from_now(0)?;
+            let valid: Vec<_> = x509_stack
+                .iter()
+                .filter(|x509| x509.not_before() <= now && x509.not_after() > now)
+                .collect();
+            if valid.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::CERT_EXPIRED,
+                    "no valid cert in chain",
+                ));
+            }
             let mut rng = rand::thread_rng();
-            let i = rng.gen_range(0..x509_stack.len() as usize);
-            Ok(x509_stack.get(i).unwrap().to_der()?)
+            let i = rng.gen_range(0..valid.len());
+            Ok(valid[i].to_der()?)
         }
         None => Err(Error::new(ErrorKind::ERROR_STACK, "not found cert chain")),
     }
 }
 
+pub struct CertExpiry {
+    pub subject: String,
+    pub not_after: String,
+    // 距离过期的天数，已过期为负数
+    pub days_left: i32,
+}
+
+/*
+   证书链中每张证书的过期时间
+*/
+pub fn cert_expiries(buff: &[u8], pass: &str) -> error::Result<Vec<CertExpiry>> {
+    let pkcs12 = Pkcs12::from_der(buff)?;
+    let parsepkcs12 = pkcs12.parse2(pass)?;
+    let now = Asn1Time::days_from_now(0)?;
+    let mut res = Vec::new();
+    for x509 in parsepkcs12.ca.iter().flatten() {
+        let diff = now.diff(x509.not_after())?;
+        let days_left = match diff.secs < 0 {
+            true => diff.days - 1,
+            false => diff.days,
+        };
+        let subject = x509
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok())
+            .unwrap_or_default();
+        res.push(CertExpiry {
+            subject: subject,
+            not_after: x509.not_after().to_string(),
+            days_left: days_left,
+        });
+    }
+    Ok(res)
+}
+
 pub fn prikey_from_pkcs12(buff: &[u8], pass: &str) -> error::Result<Vec<u8>> {
     let pkcs12 = Pkcs12::from_der(buff)?;
-    let parsepkcs12 = pkcs12.parse(pass)?;
-    Ok(parsepkcs12.pkey.private_key_to_der()?)
+    match pkcs12.parse2(pass)?.pkey {
+        Some(pkey) => Ok(pkey.private_key_to_der()?),
+        None => Err(Error::new(ErrorKind::ERROR_STACK, "not found private key")),
+    }
 }
 
 pub fn vec_append(data1: &Vec<u8>, data2: &Vec<u8>) -> Vec<u8> {